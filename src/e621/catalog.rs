@@ -0,0 +1,84 @@
+use crate::unit;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// The name of the file every download directory keeps its catalog in
+pub const CATALOG_FILE: &str = ".se621.json";
+
+/// Struct that represents the record of every post downloaded into a directory
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Catalog {
+    #[serde(default)]
+    pub posts: BTreeMap<u64, Record>,
+    /// The newest post every query had when its last download into the directory finished
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub synced: BTreeMap<String, u64>,
+}
+
+/// Struct that represents a single downloaded post
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    pub file: String,
+    pub md5: String,
+    #[serde(default)]
+    pub variant: unit::Variant,
+    #[serde(default)]
+    pub deleted: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<u64>,
+}
+
+impl Catalog {
+    /// Function that loads the catalog of a directory, directories without one get an empty catalog
+    pub fn load(dir: &Path) -> Self {
+        let data = match fs::read_to_string(dir.join(CATALOG_FILE)) {
+            Ok(data) => data,
+            Err(_) => return Catalog::default(),
+        };
+
+        match serde_json::from_str(&data) {
+            Ok(catalog) => catalog,
+            Err(e) => {
+                println!("[!] Ignoring broken catalog in {}: {}", dir.display(), e);
+                Catalog::default()
+            }
+        }
+    }
+
+    /// Function that writes the catalog back into its directory
+    pub fn save(&self, dir: &Path) {
+        fs::write(
+            dir.join(CATALOG_FILE),
+            serde_json::to_string_pretty(self).unwrap(),
+        )
+        .expect("[-] Failed to write catalog");
+    }
+
+    /// Function to check whether a post has already been downloaded into the directory and its
+    /// file is still there
+    pub fn contains(&self, dir: &Path, post_id: u64) -> bool {
+        self.posts
+            .get(&post_id)
+            .is_some_and(|r| dir.join(&r.file).exists())
+    }
+
+    /// Function that remembers the newest post of every query once its download into the
+    /// directory has finished, incremental runs only stop on posts up to it
+    pub fn mark_synced(dir: &Path, synced: &[(String, u64)]) {
+        if synced.is_empty() {
+            return;
+        }
+
+        let mut cat = Catalog::load(dir);
+        for (query, newest) in synced {
+            let mark = cat.synced.entry(query.clone()).or_insert(0);
+            *mark = (*mark).max(*newest);
+        }
+        cat.save(dir);
+    }
+}
@@ -0,0 +1,509 @@
+use crate::catalog;
+use crate::download;
+use crate::export;
+use crate::file;
+use crate::mirror;
+use crate::pool;
+use crate::preflight;
+use crate::scraper;
+use crate::unit;
+use crate::wiki;
+
+use serde_json::Value;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path;
+
+/// The amount of posts `search` prints when no limit is set
+const SEARCH_LIMIT: usize = 100;
+
+/// Function that checks and scrapes every entry, returning the Containers along with the config
+/// each one should be downloaded with
+pub async fn scrape(
+    mut tags: file::TagStore,
+    config: &unit::Config,
+) -> VecDeque<(unit::Container, unit::Config)> {
+    let mut queue = VecDeque::new();
+
+    // Entries given as urls are moved to the section matching the kind of url
+    tags.resolve_links();
+
+    // Resolve aliases and drop empty queries before any scraping starts
+    if config.preflight {
+        preflight::check_tags(&mut tags.general, config).await;
+
+        for group in &mut tags.groups {
+            let group_config = config.with_options(&group.options);
+            preflight::check_tags(&mut group.queries, &group_config).await;
+        }
+    }
+
+    // Start scraping posts
+    println!("[=] Scraping Posts");
+    for tag in tags.general {
+        let tag_config = config.with_options(&tag.options);
+        let folder = tag.options.folder.as_ref().unwrap_or(&tag.query);
+
+        download::move_legacy_folder(&tag.query, &download::sanitize(folder), &tag_config);
+        let container = scraper::build_tag_queue_in(&tag.query, folder, &tag_config).await;
+
+        if tag_config.wiki {
+            wiki::save(&tag.query, &container.tag_name, &tag_config).await;
+        }
+        queue.push_back((container, tag_config));
+    }
+
+    for group in tags.groups {
+        let group_config = config.with_options(&group.options);
+        download::move_legacy_folder(&group.name, &download::sanitize(&group.name), &group_config);
+        let container = scraper::build_group_queue(&group, &group_config).await;
+        queue.push_back((container, group_config));
+    }
+
+    for tag in tags.pools {
+        let pool_config = config.with_options(&tag.options);
+        let container = scraper::build_pool_queue(
+            tag.query
+                .parse::<u64>()
+                .expect("[-] Failed to convert pool id to integer"),
+            tag.options.folder.as_deref(),
+            &pool_config,
+        )
+        .await;
+
+        // Folders and pages are renamed before downloading so the existence check sees the
+        // right files
+        pool::find_folder(&container, &pool_config);
+        pool::reorder(&container, &pool_config);
+        queue.push_back((container, pool_config));
+    }
+
+    for tag in tags.sets {
+        let set_config = config.with_options(&tag.options);
+        let container = scraper::build_set_queue(
+            tag.query
+                .parse::<u64>()
+                .expect("[-] Failed to convert set id to integer"),
+            tag.options.folder.as_deref(),
+            &set_config,
+        )
+        .await;
+        queue.push_back((container, set_config));
+    }
+
+    for tag in tags.single_posts {
+        let post_config = config.with_options(&tag.options);
+        let container = scraper::build_single_post(
+            tag.query
+                .parse::<u64>()
+                .expect("[-] Failed to convert post id to integer"),
+            &post_config,
+        )
+        .await;
+        queue.push_back((container, post_config));
+    }
+
+    // Pool pages are named after their position so relatives are only added to the other entries
+    for (container, cfg) in queue.iter_mut() {
+        if cfg.follow.is_some() && container.pool.is_none() {
+            scraper::add_relatives(container, cfg).await;
+        }
+    }
+
+    queue
+}
+
+/// Function that downloads every Container and returns the amount of new files for each one
+fn download_all(queue: VecDeque<(unit::Container, unit::Config)>) -> Vec<(String, usize)> {
+    let mut results = Vec::new();
+    let mut pruned = 0;
+
+    println!("\n[=] Downloading Files");
+    for (mut q, q_config) in queue {
+        let names = post_names(&q);
+        let artists = export::artists(&q);
+        let len = q.queue.len();
+
+        let down = download::Downloader::new(q_config.tries, q_config.workers, &mut q.queue);
+        let downloaded = down.download(&q.tag_name, &q_config);
+
+        if len > 0 {
+            let dir = download::target_dir(&q.tag_name, len, &q_config);
+            catalog::Catalog::mark_synced(&dir, &q.synced);
+        }
+
+        if q_config.prune {
+            pruned += mirror::prune(&q.tag_name, len, &names, &q_config);
+        }
+        if let Some(meta) = &q.pool {
+            pool::write_info(meta, &q.tag_name, len, &q_config);
+        }
+        if let (Some(meta), Some(format)) = (&q.pool, q_config.export) {
+            export::pool(meta, &artists, &q.tag_name, len, format, &q_config);
+        }
+        results.push((q.tag_name, downloaded));
+    }
+
+    if pruned > 0 {
+        println!("[+] Moved {} stale files to the trash", pruned);
+    }
+
+    results
+}
+
+/// Function that collects the names of every post in a Container, which is also the name every
+/// file belonging to that post starts with
+fn post_names(container: &unit::Container) -> HashSet<String> {
+    container.queue.iter().map(|u| u.name.clone()).collect()
+}
+
+/// Function that reports what would be downloaded for every Container without writing anything
+fn dry_run(queue: VecDeque<(unit::Container, unit::Config)>) {
+    let mut total_new = 0;
+    let mut total_present = 0;
+    let mut total_bytes = 0;
+    let mut total_pruned = 0;
+
+    println!("\n[=] Dry Run");
+    for (q, q_config) in queue {
+        let cur_dir = download::target_dir(&q.tag_name, q.queue.len(), &q_config);
+
+        let mut new = 0;
+        let mut present = 0;
+        let mut missing = 0;
+        let mut bytes = 0;
+
+        for post in &q.queue {
+            if post.url.is_none() {
+                missing += 1;
+                continue;
+            }
+
+            let mut cur_file = cur_dir.clone();
+            cur_file.push(post.file_name());
+
+            if cur_file.exists() {
+                present += 1;
+                continue;
+            }
+
+            new += 1;
+            bytes += post.size;
+
+            if q_config.verbose {
+                println!("    {}", cur_file.display());
+            }
+        }
+
+        println!(
+            "[+] {}: {} posts, {} new, {} present, {} without url, {} -> {}",
+            q.tag_name,
+            q.queue.len(),
+            new,
+            present,
+            missing,
+            human_size(bytes),
+            cur_dir.display()
+        );
+
+        total_new += new;
+        total_present += present;
+        total_bytes += bytes;
+
+        if q_config.prune {
+            total_pruned += mirror::prune(&q.tag_name, q.queue.len(), &post_names(&q), &q_config);
+        }
+    }
+
+    println!(
+        "[+] Total: {} new, {} present, {} to download",
+        total_new,
+        total_present,
+        human_size(total_bytes)
+    );
+    if total_pruned > 0 {
+        println!(
+            "[+] {} stale files would be moved to the trash",
+            total_pruned
+        );
+    }
+}
+
+/// Function that runs the normal scrape and download of everything in the tags file
+pub async fn download(tags: file::TagStore, config: &unit::Config) {
+    let queue = scrape(tags, config).await;
+
+    if config.dry_run {
+        dry_run(queue);
+        return;
+    }
+
+    download_all(queue);
+}
+
+/// Function that brings an existing archive up to date and reports what was added, tags are
+/// walked newest first and stop at the first post that was already downloaded
+pub async fn sync(tags: file::TagStore, config: &unit::Config) {
+    let mut config = config.clone();
+    config.incremental = true;
+    let config = &config;
+
+    let queue = scrape(tags, config).await;
+
+    if config.dry_run {
+        dry_run(queue);
+
+        if config.mirror {
+            mirror::check(config).await;
+        }
+        return;
+    }
+
+    let results = download_all(queue);
+
+    println!("\n[=] Sync Summary");
+    let mut total = 0;
+    for (tag_name, downloaded) in results {
+        if downloaded > 0 || config.verbose {
+            println!("[+] {}: {} new", tag_name, downloaded);
+        }
+        total += downloaded;
+    }
+    println!("[+] {} new files", total);
+
+    if config.mirror {
+        mirror::check(config).await;
+    }
+}
+
+/// Function that prints the posts matching a query without downloading them
+pub async fn search(query: &str, config: &unit::Config) {
+    let mut config = config.clone();
+    config.limit.get_or_insert(SEARCH_LIMIT);
+
+    let container = scraper::build_tag_queue(query, &config).await;
+
+    println!();
+    for post in &container.queue {
+        println!(
+            "{}\t{}\t{}",
+            post.post_id,
+            post.file_name(),
+            post.url.as_deref().unwrap_or("(no url)")
+        );
+    }
+    println!("[+] {} posts", container.queue.len());
+}
+
+/// Function that prints the metadata of a post or a pool
+pub async fn info(id: u64, pool: bool, config: &unit::Config) {
+    if pool {
+        let pool = match scraper::get_pool_info(id, config).await {
+            Some(pool) => pool,
+            None => {
+                println!("[-] Pool {} doesn't exist", id);
+                std::process::exit(1);
+            }
+        };
+
+        println!("[+] Pool {}: {}", id, text(&pool["name"]));
+        println!("Category: {}", text(&pool["category"]));
+        println!("Creator: {}", text(&pool["creator_name"]));
+        println!("Active: {}", text(&pool["is_active"]));
+        println!("Updated: {}", text(&pool["updated_at"]));
+        println!("Posts: {}", list(&pool["post_ids"]));
+        println!("Description:\n{}", text(&pool["description"]));
+        return;
+    }
+
+    let post = match scraper::get_post_info(id, config).await {
+        Some(post) => post,
+        None => {
+            println!("[-] Post {} doesn't exist", id);
+            std::process::exit(1);
+        }
+    };
+
+    let file = &post["file"];
+    println!(
+        "[+] Post {}: {}.{}",
+        id,
+        text(&file["md5"]),
+        text(&file["ext"])
+    );
+    println!(
+        "Size: {}x{}, {} bytes",
+        text(&file["width"]),
+        text(&file["height"]),
+        text(&file["size"])
+    );
+    println!("URL: {}", text(&file["url"]));
+    println!("Rating: {}", text(&post["rating"]));
+    println!("Score: {}", text(&post["score"]["total"]));
+    println!("Favorites: {}", text(&post["fav_count"]));
+    println!("Created: {}", text(&post["created_at"]));
+    println!("Parent: {}", text(&post["relationships"]["parent_id"]));
+    println!("Children: {}", list(&post["relationships"]["children"]));
+    println!("Pools: {}", list(&post["pools"]));
+
+    if let Some(categories) = post["tags"].as_object() {
+        for (category, tags) in categories {
+            if !list(tags).is_empty() {
+                println!("Tags ({}): {}", category, list(tags));
+            }
+        }
+    }
+
+    println!("Sources: {}", list(&post["sources"]));
+}
+
+/// Function that checks every file named after its md5 against its contents
+pub fn verify(config: &unit::Config, delete: bool) {
+    let base = download::base_dir(config);
+    println!("[=] Verifying: {}", base.display());
+
+    let mut checked = 0;
+    let mut corrupt = 0;
+    let mut renditions: HashMap<path::PathBuf, HashSet<String>> = HashMap::new();
+
+    for file_path in walk(&base) {
+        let stem = match file_path.file_stem().and_then(|s| s.to_str()) {
+            Some(stem) => stem,
+            None => continue,
+        };
+
+        // Pool pages and sidecar files aren't named after their md5 so they can't be checked
+        if stem.len() != 32 || !stem.chars().all(|c| c.is_ascii_hexdigit()) {
+            continue;
+        }
+        if file_path.extension().is_some_and(|e| e == "json") {
+            continue;
+        }
+
+        // Samples and previews are named after the md5 of the original
+        let dir = file_path.parent().unwrap().to_path_buf();
+        let skip = renditions.entry(dir.clone()).or_insert_with(|| {
+            catalog::Catalog::load(&dir)
+                .posts
+                .into_values()
+                .filter(|r| r.variant != unit::Variant::Original)
+                .map(|r| r.file)
+                .collect()
+        });
+        if skip.contains(file_path.file_name().unwrap().to_str().unwrap_or("")) {
+            continue;
+        }
+
+        let data = fs::read(&file_path).expect("[-] Failed to read file");
+        checked += 1;
+
+        if format!("{:x}", md5::compute(&data)) != stem {
+            corrupt += 1;
+            println!("[-] Corrupt: {}", file_path.display());
+
+            if delete {
+                fs::remove_file(&file_path).expect("[-] Failed to remove corrupt file");
+            }
+        } else if config.verbose {
+            println!("[+] Ok: {}", file_path.display());
+        }
+    }
+
+    println!("[+] Checked {} files, {} corrupt", checked, corrupt);
+    if corrupt > 0 && delete {
+        println!("[+] Removed corrupt files, they will be downloaded again on the next run");
+    }
+}
+
+/// Function that prints the amount of files and space used by every directory in the archive
+pub fn stats(config: &unit::Config) {
+    let base = download::base_dir(config);
+    println!("[=] Archive: {}", base.display());
+
+    let mut entries: Vec<path::PathBuf> = match fs::read_dir(&base) {
+        Ok(dir) => dir.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+        Err(_) => {
+            println!("[-] Nothing has been downloaded yet");
+            return;
+        }
+    };
+    entries.sort();
+
+    let mut total_files = 0;
+    let mut total_bytes = 0;
+
+    for entry in entries {
+        let (files, bytes) = walk(&entry)
+            .iter()
+            .filter(|f| !f.ends_with(catalog::CATALOG_FILE))
+            .filter_map(|f| f.metadata().ok())
+            .fold((0, 0), |(files, bytes), m| (files + 1, bytes + m.len()));
+
+        if entry.is_dir() {
+            println!(
+                "{}: {} files, {}",
+                entry.file_name().unwrap().to_string_lossy(),
+                files,
+                human_size(bytes)
+            );
+        }
+
+        total_files += files;
+        total_bytes += bytes;
+    }
+
+    println!(
+        "[+] Total: {} files, {}",
+        total_files,
+        human_size(total_bytes)
+    );
+}
+
+/// Function that collects every file under a path
+fn walk(root: &path::Path) -> Vec<path::PathBuf> {
+    let mut files = Vec::new();
+
+    if root.is_file() {
+        files.push(root.to_path_buf());
+        return files;
+    }
+
+    if let Ok(dir) = fs::read_dir(root) {
+        for entry in dir.filter_map(|e| e.ok()) {
+            files.extend(walk(&entry.path()));
+        }
+    }
+
+    files
+}
+
+/// Function that formats a size in bytes for printing
+fn human_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, units[unit])
+}
+
+/// Function that formats a json value for printing
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::from("none"),
+        v => v.to_string(),
+    }
+}
+
+/// Function that formats a json array for printing
+fn list(value: &Value) -> String {
+    value
+        .as_array()
+        .map(|a| a.iter().map(text).collect::<Vec<String>>().join(" "))
+        .unwrap_or_default()
+}
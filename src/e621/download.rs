@@ -1,3 +1,8 @@
+use crate::catalog;
+use crate::dtext;
+use crate::notes;
+use crate::scraper;
+use crate::sources;
 use crate::unit;
 use crossbeam;
 use crossbeam::channel;
@@ -5,6 +10,7 @@ use std::collections::VecDeque;
 use std::env;
 use std::fs;
 use std::io;
+use std::path::PathBuf;
 
 /// A struct used for storing information needed to download files
 pub struct Downloader {
@@ -33,29 +39,14 @@ impl Downloader {
         }
     }
 
-    /// The function which downloads all of the images to a specified directory
-    pub fn download(&self, tag_dir: &str, config: &unit::Config) {
+    /// The function which downloads all of the images to a specified directory and returns
+    /// the number of new files
+    pub fn download(&self, tag_dir: &str, config: &unit::Config) -> usize {
         crossbeam::thread::scope(|thread_scope| {
             let mut handles = Vec::new();
 
             // Setup the directory to download into
-            let mut cur_dir = std::path::PathBuf::new();
-
-            if let Some(down_dir) = &config.directory {
-                cur_dir.push(down_dir);
-            } else {
-                cur_dir = env::current_dir().unwrap();
-            }
-
-            if config.sfw {
-                cur_dir.push("sfw-downloads")
-            } else {
-                cur_dir.push("downloads");
-            }
-
-            if self.len > 1 {
-                cur_dir.push(&tag_dir);
-            }
+            let cur_dir = target_dir(tag_dir, self.len, config);
 
             println!("[+] Downloading / Updating: {}", tag_dir);
 
@@ -76,8 +67,11 @@ impl Downloader {
 
                 // Workers start executing here
                 let handle = thread_scope.spawn(move |_| {
+                    let mut downloaded = 0;
+                    let mut records = Vec::new();
                     let down_client = reqwest::blocking::ClientBuilder::new()
                         .user_agent(crate::APP_USER_AGENT)
+                        .default_headers(scraper::auth_headers(config))
                         .build()
                         .unwrap();
 
@@ -91,24 +85,64 @@ impl Downloader {
                         };
 
                         if cur_unit.url.is_none() {
+                            println!("[-] Skipping post {}: no file url", cur_unit.post_id);
                             continue;
                         }
 
+                        // Write the post metadata next to the file if it was requested
+                        if let Some(meta) = &cur_unit.meta {
+                            let mut sidecar = cur_dir.clone();
+                            sidecar.push(format!("{}.json", cur_unit.name));
+                            if !sidecar.exists() {
+                                fs::write(&sidecar, serde_json::to_string_pretty(meta).unwrap())
+                                    .expect("[-] Failed to write sidecar file");
+                            }
+                        }
+
+                        if let Some(commentary) = &cur_unit.commentary {
+                            let mut sidecar = cur_dir.clone();
+                            sidecar.push(format!("{}.commentary.md", cur_unit.name));
+                            if !sidecar.exists() {
+                                fs::write(&sidecar, dtext::to_markdown(commentary) + "\n")
+                                    .expect("[-] Failed to write commentary file");
+                            }
+                        }
+
+                        notes::save(&cur_unit, &cur_dir, &down_client, config);
+                        sources::save(&cur_unit, &cur_dir, config);
+
+                        let file_name = cur_unit.file_name();
+                        let record = catalog::Record {
+                            file: file_name.clone(),
+                            md5: cur_unit.md5.clone(),
+                            variant: cur_unit.variant,
+                            deleted: false,
+                            parent: cur_unit.parent,
+                            children: cur_unit.children.clone(),
+                        };
+
                         let mut cur_file = cur_dir.clone();
-                        cur_file.push(format!("{}.{}", cur_unit.name, cur_unit.ext));
+                        cur_file.push(&file_name);
                         if cur_file.exists() {
+                            records.push((cur_unit.post_id, record));
+                            sources::run_command(&cur_unit, &cur_file, config);
                             continue;
                         }
 
                         // Try and download a file a certain amount of times
                         while retry_counter < self.tries {
                             let response = down_client.get(cur_unit.url.as_ref().unwrap()).send();
-                            if response.is_err() {
-                                retry_counter += 1;
-                                continue;
-                            }
 
-                            let resp = match response.unwrap().bytes() {
+                            // Error pages would otherwise be saved and recorded as the file
+                            let response = match response {
+                                Ok(response) if response.status().is_success() => response,
+                                _ => {
+                                    retry_counter += 1;
+                                    continue;
+                                }
+                            };
+
+                            let resp = match response.bytes() {
                                 Err(_) => {
                                     retry_counter += 1;
                                     continue;
@@ -122,26 +156,151 @@ impl Downloader {
                             io::copy(&mut cur, &mut o_file).unwrap();
 
                             if config.verbose {
-                                println!("Thread {}: {}.{}", &x, cur_unit.name, cur_unit.ext);
+                                println!("Thread {}: {}", &x, file_name);
                             }
 
+                            downloaded += 1;
+                            records.push((cur_unit.post_id, record));
+                            sources::run_command(&cur_unit, &cur_file, config);
                             break;
                         }
                         retry_counter = 0;
                         chan_counter = 0;
                     }
+
+                    (downloaded, records)
                 });
                 handles.push(handle);
             }
 
             // Wait until all threads are complete
+            let mut downloaded = 0;
+            let mut records = Vec::new();
             for h in handles {
-                h.join().unwrap();
+                let (count, thread_records) = h.join().unwrap();
+                downloaded += count;
+                records.extend(thread_records);
             }
             if config.verbose {
                 println!("Finished threads");
             }
+
+            // Remember what is in the directory so later runs can stop early
+            if !records.is_empty() {
+                let mut cat = catalog::Catalog::load(&cur_dir);
+                cat.posts.extend(records);
+                cat.save(&cur_dir);
+            }
+
+            downloaded
         })
-        .unwrap();
+        .unwrap()
     }
 }
+
+/// Function that gets the directory every tag directory is created in
+pub fn base_dir(config: &unit::Config) -> PathBuf {
+    let mut cur_dir = PathBuf::new();
+
+    if let Some(down_dir) = &config.directory {
+        cur_dir.push(down_dir);
+    } else {
+        cur_dir = env::current_dir().unwrap();
+    }
+
+    if config.sfw {
+        cur_dir.push("sfw-downloads")
+    } else {
+        cur_dir.push("downloads");
+    }
+
+    cur_dir
+}
+
+/// Function that turns a query or name into one that can be used for a folder on every
+/// filesystem, long names are cut short and get a hash of the full name so they stay unique
+pub fn sanitize(name: &str) -> String {
+    // Characters that aren't allowed are swapped for lookalikes so `score:>10` and `score:<10`
+    // still end up in different folders
+    let clean: String = name
+        .chars()
+        .map(|c| match c {
+            '/' => '⧸',
+            '\\' => '⧹',
+            ':' => '：',
+            '*' => '＊',
+            '?' => '？',
+            '"' => '＂',
+            '<' => '＜',
+            '>' => '＞',
+            '|' => '｜',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .collect();
+
+    // Windows doesn't allow names ending in a dot and leading dots hide the folder
+    let clean = clean.split_whitespace().collect::<Vec<&str>>().join(" ");
+    let mut clean = String::from(clean.trim_start_matches('.').trim_end_matches('.'));
+
+    // Filesystems limit names by bytes rather than characters
+    if clean.len() > crate::MAX_FOLDER_LEN {
+        let hash = format!("{:x}", md5::compute(name));
+        let mut end = crate::MAX_FOLDER_LEN - 9;
+        while !clean.is_char_boundary(end) {
+            end -= 1;
+        }
+        clean = format!(
+            "{} {}",
+            clean[..end].trim_end_matches(['.', ' ']),
+            &hash[..8]
+        );
+    }
+
+    if clean.is_empty() {
+        String::from("_")
+    } else {
+        clean
+    }
+}
+
+/// Function that moves a folder named after the raw query, as earlier versions did, to the
+/// sanitised name so it doesn't get downloaded again
+pub fn move_legacy_folder(query: &str, folder: &str, config: &unit::Config) {
+    let base = base_dir(config);
+    let old = base.join(query);
+    let new = base.join(folder);
+
+    if query == folder || query.is_empty() || new.exists() || !old.is_dir() {
+        return;
+    }
+
+    println!("[+] Moving folder: {} -> {}", query, folder);
+    if !config.dry_run {
+        fs::rename(&old, &new).expect("[-] Failed to move folder");
+    }
+}
+
+/// Function that gets the directory earlier runs downloaded the files of a tag to, before the
+/// amount of posts is known, tags without a folder had a single file placed in the base directory
+pub fn existing_dir(tag_dir: &str, config: &unit::Config) -> PathBuf {
+    let dir = base_dir(config).join(tag_dir);
+
+    if dir.is_dir() {
+        dir
+    } else {
+        base_dir(config)
+    }
+}
+
+/// Function that gets the directory the files of a tag are downloaded to, single files are
+/// placed directly in the base directory
+pub fn target_dir(tag_dir: &str, len: usize, config: &unit::Config) -> PathBuf {
+    let mut cur_dir = base_dir(config);
+
+    if len > 1 {
+        cur_dir.push(tag_dir);
+    }
+
+    cur_dir
+}
@@ -0,0 +1,94 @@
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+/// Macro that compiles a pattern the first time it is used and reuses it on every later call
+macro_rules! regex {
+    ($pattern:expr) => {{
+        static RE: Lazy<Regex> = Lazy::new(|| Regex::new($pattern).unwrap());
+        &*RE
+    }};
+}
+
+/// Function that converts the DText markup e621 uses for descriptions and wiki pages into
+/// markdown, which also reads fine as plain text
+pub fn to_markdown(text: &str) -> String {
+    let mut text = text.replace("\r\n", "\n");
+
+    // Code blocks are converted first so nothing inside them is touched by the rules below
+    text = replace(
+        &text,
+        regex!(r"(?s)\[code\]\n?(.*?)\n?\[/code\]"),
+        "```\n$1\n```",
+    );
+
+    text = replace_with(
+        &text,
+        regex!(r"(?s)\[quote\]\n?(.*?)\n?\[/quote\]"),
+        |caps| {
+            caps[1]
+                .lines()
+                .map(|line| format!("> {}", line))
+                .collect::<Vec<String>>()
+                .join("\n")
+        },
+    );
+
+    text = replace(&text, regex!(r"(?s)\[b\](.*?)\[/b\]"), "**$1**");
+    text = replace(&text, regex!(r"(?s)\[i\](.*?)\[/i\]"), "*$1*");
+    text = replace(&text, regex!(r"(?s)\[s\](.*?)\[/s\]"), "~~$1~~");
+
+    // Markup that has no markdown counterpart is dropped and only the text is kept
+    text = replace(
+        &text,
+        regex!(r"\[/?(u|o|sup|sub|tn|spoiler|color(=[^\]]*)?)\]"),
+        "",
+    );
+
+    text = replace(
+        &text,
+        regex!(r"\[section(,expanded)?=([^\]]*)\]"),
+        "\n### $2\n",
+    );
+    text = replace(&text, regex!(r"\[section(,expanded)?\]|\[/section\]"), "");
+    text = replace(&text, regex!(r"\[hr\]"), "---");
+    text = replace(&text, regex!(r"\[br\]"), "\n");
+
+    text = replace_with(&text, regex!(r"(?m)^h([1-6])\.\s*"), |caps| {
+        format!("{} ", "#".repeat(caps[1].parse().unwrap()))
+    });
+    text = replace_with(&text, regex!(r"(?m)^(\*+) "), |caps| {
+        format!("{}* ", "  ".repeat(caps[1].len() - 1))
+    });
+
+    // Links can point at a full url or a path on e621
+    text = replace_with(
+        &text,
+        regex!(r#""([^"\n]+)":(?:\[([^\]]+)\]|((?:https?://|/)[^\s\]]+))"#),
+        |caps| {
+            let url = caps.get(2).or_else(|| caps.get(3)).unwrap().as_str();
+            if url.starts_with('/') {
+                format!("[{}](https://e621.net{})", &caps[1], url)
+            } else {
+                format!("[{}]({})", &caps[1], url)
+            }
+        },
+    );
+
+    text = replace(&text, regex!(r"\[\[[^\]|]+\|([^\]]+)\]\]"), "$1");
+    text = replace(&text, regex!(r"\[\[([^\]]+)\]\]"), "$1");
+    text = replace(&text, regex!(r"\{\{([^}]+)\}\}"), "$1");
+
+    text.trim().to_string()
+}
+
+/// Function that replaces every match of a pattern
+fn replace(text: &str, pattern: &Regex, with: &str) -> String {
+    pattern.replace_all(text, with).into_owned()
+}
+
+/// Function that replaces every match of a pattern with the result of a function
+fn replace_with(text: &str, pattern: &Regex, with: impl Fn(&Captures) -> String) -> String {
+    pattern
+        .replace_all(text, |caps: &Captures| with(caps))
+        .into_owned()
+}
@@ -0,0 +1,632 @@
+use crate::catalog;
+use crate::download;
+use crate::dtext;
+use crate::unit;
+
+use chrono::{DateTime, Utc};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::GenericImageView;
+use pdf_writer::{Content, Filter, Name, Rect, Ref, TextStr};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// The directory exported pools are written to
+pub const EXPORT_DIR: &str = "exports";
+
+/// File types that can be shown as a page
+const IMAGE_EXTS: [&str; 5] = ["jpg", "jpeg", "png", "gif", "webp"];
+
+/// Artist tags that don't name an artist
+const NOT_ARTISTS: [&str; 4] = [
+    "conditional_dnp",
+    "sound_warning",
+    "unknown_artist",
+    "anonymous_artist",
+];
+
+/// PDF viewers don't support pages larger than this many points
+const MAX_PDF_SIZE: f32 = 14400.0;
+
+/// The file in the export directory that lists the pages every export was written from
+const STATE_FILE: &str = ".se621-exports.json";
+
+/// Struct that represents a downloaded page of a pool
+struct Page {
+    number: usize,
+    post_id: u64,
+    path: PathBuf,
+    ext: String,
+    name: String,
+}
+
+/// Function that collects the artists of every post in a pool
+pub fn artists(container: &unit::Container) -> Vec<String> {
+    container
+        .queue
+        .iter()
+        .flat_map(|u| u.artists.iter())
+        .filter(|a| !NOT_ARTISTS.contains(&a.as_str()))
+        .cloned()
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect()
+}
+
+/// Function that packages a downloaded pool into the export format set in the config, the export
+/// is only written again when pages have been added or changed since it was last written
+pub fn pool(
+    pool: &Value,
+    artists: &[String],
+    tag_dir: &str,
+    len: usize,
+    format: unit::Export,
+    config: &unit::Config,
+) {
+    let dir = download::target_dir(tag_dir, len, config);
+    let (pages, skipped) = pages(pool, &dir);
+    if pages.is_empty() {
+        return;
+    }
+
+    let out_dir = download::base_dir(config).join(EXPORT_DIR);
+    fs::create_dir_all(&out_dir).expect("[-] Failed to create export directory");
+
+    let ext = extension(format);
+    let path = out_dir.join(format!("{}.{}", tag_dir, ext));
+
+    // Pages that can't be decoded are left out of epubs and pdfs, they are still listed here so
+    // the export isn't written again every run because of them
+    let names: BTreeSet<String> = pages.iter().map(|p| p.name.clone()).collect();
+    let file_name = format!("{}.{}", tag_dir, ext);
+    let mut state = load_state(&out_dir);
+    if state.get(&file_name) == Some(&names) && is_newer(&path, &pages) {
+        if config.verbose {
+            println!("[+] Export of {} is up to date", tag_dir);
+        }
+        return;
+    }
+
+    for page in skipped {
+        println!(
+            "[!] Leaving page {} (post {}) out of the {}: {} isn't an image",
+            page.number, page.post_id, ext, page.ext
+        );
+    }
+
+    // The export is written next to the old one and swapped in once it is complete
+    let tmp_path = path.with_extension(format!("{}.tmp", ext));
+    let written = match format {
+        unit::Export::Cbz => write_cbz(&tmp_path, pool, artists, &pages),
+        unit::Export::Epub => write_epub(&tmp_path, pool, artists, &pages),
+        unit::Export::Pdf => write_pdf(&tmp_path, pool, artists, &pages),
+    };
+
+    match written.and_then(|_| fs::rename(&tmp_path, &path)) {
+        Ok(_) => {
+            println!(
+                "[+] Exported {} pages of {} to {}",
+                pages.len(),
+                tag_dir,
+                path.display()
+            );
+
+            state.insert(file_name, names);
+            fs::write(
+                out_dir.join(STATE_FILE),
+                serde_json::to_string_pretty(&state).unwrap(),
+            )
+            .expect("[-] Failed to write export state");
+        }
+        Err(e) => {
+            fs::remove_file(&tmp_path).ok();
+            println!("[-] Failed to export {}: {}", tag_dir, e);
+        }
+    }
+}
+
+/// Function that gets the file extension of an export format
+fn extension(format: unit::Export) -> &'static str {
+    match format {
+        unit::Export::Cbz => "cbz",
+        unit::Export::Epub => "epub",
+        unit::Export::Pdf => "pdf",
+    }
+}
+
+/// Function that finds the downloaded file of every page of a pool in the order of the pool,
+/// returning the images and the pages that aren't images separately
+fn pages(pool: &Value, dir: &Path) -> (Vec<Page>, Vec<Page>) {
+    let cat = catalog::Catalog::load(dir);
+    let post_ids: Vec<u64> = pool["post_ids"]
+        .as_array()
+        .map(|ids| ids.iter().filter_map(|id| id.as_u64()).collect())
+        .unwrap_or_default();
+
+    // Pages are zero padded so they sort in the right order in every reader
+    let width = post_ids.len().to_string().len();
+
+    post_ids
+        .into_iter()
+        .enumerate()
+        .filter_map(|(number, post_id)| {
+            let record = cat.posts.get(&post_id)?;
+            let path = dir.join(&record.file);
+            if !path.exists() {
+                return None;
+            }
+
+            let ext = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or("")
+                .to_lowercase();
+
+            Some(Page {
+                number,
+                post_id,
+                name: format!("{:0width$}.{}", number, ext, width = width),
+                path,
+                ext,
+            })
+        })
+        .partition(|page| IMAGE_EXTS.contains(&page.ext.as_str()))
+}
+
+/// Function that loads the pages every export in the export directory was written from
+fn load_state(out_dir: &Path) -> BTreeMap<String, BTreeSet<String>> {
+    fs::read_to_string(out_dir.join(STATE_FILE))
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+/// Function to check whether an export was written after every one of its pages
+fn is_newer(path: &Path, pages: &[Page]) -> bool {
+    let written = match path.metadata().and_then(|m| m.modified()) {
+        Ok(written) => written,
+        Err(_) => return false,
+    };
+
+    pages.iter().all(|page| {
+        page.path
+            .metadata()
+            .and_then(|m| m.modified())
+            .is_ok_and(|m| m <= written)
+    })
+}
+
+/// Function that writes the pages of a pool into a cbz with a ComicInfo.xml
+fn write_cbz(path: &Path, pool: &Value, artists: &[String], pages: &[Page]) -> io::Result<()> {
+    let mut archive = zip::ZipWriter::new(fs::File::create(path)?);
+
+    archive.start_file("ComicInfo.xml", zip::write::FileOptions::default())?;
+    archive.write_all(comic_info(pool, artists, pages.len()).as_bytes())?;
+
+    // Images are already compressed so they are stored as they are
+    let stored =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    for page in pages {
+        archive.start_file(page.name.as_str(), stored)?;
+        archive.write_all(&fs::read(&page.path)?)?;
+    }
+
+    archive.finish()?;
+    Ok(())
+}
+
+/// Function that builds the ComicInfo.xml comic readers take the metadata of a cbz from
+fn comic_info(pool: &Value, artists: &[String], page_count: usize) -> String {
+    let post_ids = pool["post_ids"]
+        .as_array()
+        .map(|ids| {
+            ids.iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        })
+        .unwrap_or_default();
+    let artists = artists.join(", ");
+
+    let fields = [
+        ("Title", title(pool)),
+        ("Series", title(pool)),
+        ("Summary", description(pool)),
+        ("Writer", artists.clone()),
+        ("Penciller", artists),
+        ("PageCount", page_count.to_string()),
+        ("Web", format!("https://e621.net/pools/{}", pool["id"])),
+        ("Notes", format!("Post ids: {}", post_ids)),
+    ];
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<ComicInfo xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\">\n",
+    );
+    for (field, value) in fields.iter().filter(|(_, v)| !v.is_empty()) {
+        xml.push_str(&format!("  <{0}>{1}</{0}>\n", field, escape_xml(value)));
+    }
+    xml.push_str("</ComicInfo>\n");
+
+    xml
+}
+
+/// Function that writes the pages of a pool into a fixed layout epub with one image per page
+fn write_epub(path: &Path, pool: &Value, artists: &[String], pages: &[Page]) -> io::Result<()> {
+    let mut archive = zip::ZipWriter::new(fs::File::create(path)?);
+    let stored =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let deflated = zip::write::FileOptions::default();
+
+    // The mimetype has to be the first file and can't be compressed
+    archive.start_file("mimetype", stored)?;
+    archive.write_all(b"application/epub+zip")?;
+
+    archive.start_file("META-INF/container.xml", deflated)?;
+    archive.write_all(
+        br#"<?xml version="1.0" encoding="utf-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#,
+    )?;
+
+    let mut manifest = String::new();
+    let mut spine = String::new();
+    let mut toc = String::new();
+
+    for page in pages {
+        let (width, height) = match image::image_dimensions(&page.path) {
+            Ok(dimensions) => dimensions,
+            Err(e) => {
+                println!(
+                    "[!] Leaving page {} (post {}) out of the epub: {}",
+                    page.number, page.post_id, e
+                );
+                continue;
+            }
+        };
+
+        let id = format!("p{}", page.number);
+        let label = format!("Page {}", page.number + 1);
+
+        archive.start_file(format!("OEBPS/images/{}", page.name), stored)?;
+        archive.write_all(&fs::read(&page.path)?)?;
+
+        archive.start_file(format!("OEBPS/pages/{}.xhtml", id), deflated)?;
+        archive.write_all(
+            format!(
+                r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head>
+  <title>{label}</title>
+  <meta name="viewport" content="width={width}, height={height}"/>
+  <style>body {{ margin: 0; }} img {{ width: 100%; height: 100%; }}</style>
+</head>
+<body><img src="../images/{name}" alt="{label}"/></body>
+</html>
+"#,
+                label = label,
+                width = width,
+                height = height,
+                name = page.name
+            )
+            .as_bytes(),
+        )?;
+
+        // The first page doubles as the cover
+        let cover = if manifest.is_empty() {
+            r#" properties="cover-image""#
+        } else {
+            ""
+        };
+        manifest.push_str(&format!(
+            "    <item id=\"img-{0}\" href=\"images/{1}\" media-type=\"{2}\"{3}/>\n    <item id=\"{0}\" href=\"pages/{0}.xhtml\" media-type=\"application/xhtml+xml\"/>\n",
+            id,
+            page.name,
+            media_type(&page.ext),
+            cover
+        ));
+        spine.push_str(&format!("    <itemref idref=\"{}\"/>\n", id));
+        toc.push_str(&format!(
+            "      <li><a href=\"pages/{}.xhtml\">{}</a></li>\n",
+            id, label
+        ));
+    }
+
+    let creators: String = creators(pool, artists)
+        .iter()
+        .map(|c| format!("    <dc:creator>{}</dc:creator>\n", escape_xml(c)))
+        .collect();
+
+    // Epub wants the modification time in UTC without fractions
+    let modified = pool["updated_at"]
+        .as_str()
+        .and_then(|updated| DateTime::parse_from_rfc3339(updated).ok())
+        .map_or(String::from("1970-01-01T00:00:00Z"), |updated| {
+            updated
+                .with_timezone(&Utc)
+                .format("%Y-%m-%dT%H:%M:%SZ")
+                .to_string()
+        });
+
+    archive.start_file("OEBPS/content.opf", deflated)?;
+    archive.write_all(
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id" prefix="rendition: http://www.idpf.org/vocab/rendition/#">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="id">https://e621.net/pools/{id}</dc:identifier>
+    <dc:title>{title}</dc:title>
+{creators}    <dc:description>{description}</dc:description>
+    <dc:language>en</dc:language>
+    <meta property="dcterms:modified">{modified}</meta>
+    <meta property="rendition:layout">pre-paginated</meta>
+    <meta property="rendition:spread">none</meta>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+{manifest}  </manifest>
+  <spine>
+{spine}  </spine>
+</package>
+"#,
+            id = pool["id"],
+            title = escape_xml(&title(pool)),
+            creators = creators,
+            description = escape_xml(&description(pool)),
+            modified = modified,
+            manifest = manifest,
+            spine = spine
+        )
+        .as_bytes(),
+    )?;
+
+    archive.start_file("OEBPS/nav.xhtml", deflated)?;
+    archive.write_all(
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head><title>{title}</title></head>
+<body>
+  <nav epub:type="toc">
+    <ol>
+{toc}    </ol>
+  </nav>
+</body>
+</html>
+"#,
+            title = escape_xml(&title(pool)),
+            toc = toc
+        )
+        .as_bytes(),
+    )?;
+
+    archive.finish()?;
+    Ok(())
+}
+
+/// Function that writes the pages of a pool into a pdf with one image per page
+fn write_pdf(path: &Path, pool: &Value, artists: &[String], pages: &[Page]) -> io::Result<()> {
+    let mut pdf = pdf_writer::Pdf::new();
+    let catalog_id = Ref::new(1);
+    let tree_id = Ref::new(2);
+    let info_id = Ref::new(3);
+    let mut next_id = 4;
+    let mut kids = Vec::new();
+
+    for page in pages {
+        let image = match pdf_image(&fs::read(&page.path)?, &page.ext) {
+            Ok(image) => image,
+            Err(e) => {
+                println!(
+                    "[!] Leaving page {} (post {}) out of the pdf: {}",
+                    page.number, page.post_id, e
+                );
+                continue;
+            }
+        };
+
+        let (image_id, content_id, page_id) = (
+            Ref::new(next_id),
+            Ref::new(next_id + 1),
+            Ref::new(next_id + 2),
+        );
+        next_id += 3;
+
+        // Objects are written out once their writer goes out of scope
+        {
+            let mut xobject = pdf.image_xobject(image_id, &image.data);
+            xobject.filter(image.filter);
+            xobject.width(image.width as i32);
+            xobject.height(image.height as i32);
+            xobject.bits_per_component(8);
+            match image.components {
+                1 => xobject.color_space().device_gray(),
+                4 => xobject.color_space().device_cmyk(),
+                _ => xobject.color_space().device_rgb(),
+            }
+            // Adobe software writes cmyk jpgs with every channel inverted
+            if image.inverted {
+                xobject.decode([1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0]);
+            }
+        }
+
+        // Pages are the size of the image unless that is too large for pdf viewers
+        let scale = (MAX_PDF_SIZE / image.width.max(image.height) as f32).min(1.0);
+        let (width, height) = (image.width as f32 * scale, image.height as f32 * scale);
+
+        let mut content = Content::new();
+        content.save_state();
+        content.transform([width, 0.0, 0.0, height, 0.0, 0.0]);
+        content.x_object(Name(b"Im0"));
+        content.restore_state();
+        pdf.stream(content_id, &content.finish());
+
+        {
+            let mut pdf_page = pdf.page(page_id);
+            pdf_page.parent(tree_id);
+            pdf_page.media_box(Rect::new(0.0, 0.0, width, height));
+            pdf_page.contents(content_id);
+            pdf_page
+                .resources()
+                .x_objects()
+                .pair(Name(b"Im0"), image_id);
+        }
+
+        kids.push(page_id);
+    }
+
+    pdf.catalog(catalog_id).pages(tree_id);
+    pdf.pages(tree_id).count(kids.len() as i32).kids(kids);
+    pdf.document_info(info_id)
+        .title(TextStr(&title(pool)))
+        .author(TextStr(&creators(pool, artists).join(", ")))
+        .subject(TextStr(&description(pool)))
+        .creator(TextStr("se621"));
+
+    fs::write(path, pdf.finish())
+}
+
+/// Struct that represents an image ready to be embedded in a pdf
+struct PdfImage {
+    data: Vec<u8>,
+    filter: Filter,
+    components: u8,
+    inverted: bool,
+    width: u32,
+    height: u32,
+}
+
+/// Function that turns an image into one that can be embedded in a pdf, jpgs are embedded as they
+/// are and everything else is stored as compressed rgb
+fn pdf_image(data: &[u8], ext: &str) -> image::ImageResult<PdfImage> {
+    if ext == "jpg" || ext == "jpeg" {
+        if let Some(jpg) = jpeg_info(data) {
+            return Ok(PdfImage {
+                data: data.to_vec(),
+                filter: Filter::DctDecode,
+                ..jpg
+            });
+        }
+    }
+
+    let image = image::load_from_memory(data)?;
+    let (width, height) = image.dimensions();
+
+    // Transparent parts are shown on white like they are on the site
+    let mut rgb = Vec::with_capacity((width * height * 3) as usize);
+    for pixel in image.to_rgba8().pixels() {
+        let alpha = pixel[3] as u32;
+        for channel in &pixel.0[..3] {
+            rgb.push(((*channel as u32 * alpha + 255 * (255 - alpha)) / 255) as u8);
+        }
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&rgb)?;
+
+    Ok(PdfImage {
+        data: encoder.finish()?,
+        filter: Filter::FlateDecode,
+        components: 3,
+        inverted: false,
+        width,
+        height,
+    })
+}
+
+/// Function that reads the size and color channels of a jpg from its headers, jpgs that pdf
+/// viewers can't show as they are give None so they get converted instead
+fn jpeg_info(data: &[u8]) -> Option<PdfImage> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut adobe = false;
+    let mut pos = 2;
+
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+
+        let marker = data[pos + 1];
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let segment = data.get(pos + 4..pos + 2 + len)?;
+
+        match marker {
+            // The Adobe segment marks cmyk data that is stored inverted
+            0xEE if segment.starts_with(b"Adobe") => adobe = true,
+            // Every start of frame marker except the ones for huffman and arithmetic tables
+            0xC0..=0xCF if marker != 0xC4 && marker != 0xC8 && marker != 0xCC => {
+                let components = *segment.get(5)?;
+                if segment[0] != 8 || ![1, 3, 4].contains(&components) {
+                    return None;
+                }
+
+                return Some(PdfImage {
+                    data: Vec::new(),
+                    filter: Filter::DctDecode,
+                    components,
+                    inverted: adobe && components == 4,
+                    width: u16::from_be_bytes([segment[3], segment[4]]) as u32,
+                    height: u16::from_be_bytes([segment[1], segment[2]]) as u32,
+                });
+            }
+            // The image data starts without a frame header having been found
+            0xDA => return None,
+            _ => {}
+        }
+
+        pos += 2 + len;
+    }
+
+    None
+}
+
+/// Function that gets the title of a pool
+fn title(pool: &Value) -> String {
+    pool["name"].as_str().unwrap_or("").replace('_', " ")
+}
+
+/// Function that gets the description of a pool
+fn description(pool: &Value) -> String {
+    dtext::to_markdown(pool["description"].as_str().unwrap_or(""))
+}
+
+/// Function that gets who made a pool, the artists of its posts or otherwise whoever created it
+fn creators(pool: &Value, artists: &[String]) -> Vec<String> {
+    if !artists.is_empty() {
+        return artists.to_vec();
+    }
+
+    pool["creator_name"]
+        .as_str()
+        .map(|c| vec![c.to_string()])
+        .unwrap_or_default()
+}
+
+/// Function that gets the media type of an image for the epub manifest
+fn media_type(ext: &str) -> &'static str {
+    match ext {
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        _ => "image/jpeg",
+    }
+}
+
+/// Function that escapes the characters xml doesn't allow in text
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -1,3 +1,6 @@
+use crate::link;
+use crate::unit;
+
 use std::env;
 use std::fs;
 use std::io;
@@ -7,18 +10,178 @@ use std::path;
 /// Structure for representing the values recovered from the tags file
 #[derive(Debug)]
 pub struct TagStore {
-    pub general: Vec<String>,
-    pub pools: Vec<String>,
-    pub single_posts: Vec<String>,
+    pub general: Vec<Entry>,
+    pub pools: Vec<Entry>,
+    pub single_posts: Vec<Entry>,
+    pub sets: Vec<Entry>,
+    pub groups: Vec<Group>,
+}
+
+/// Structure for representing a named group of queries downloaded into a single folder
+#[derive(Debug)]
+pub struct Group {
+    pub name: String,
+    pub options: EntryOptions,
+    pub queries: Vec<Entry>,
+}
+
+/// Structure for representing a single line of the tags file
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub query: String,
+    pub options: EntryOptions,
+}
+
+/// Structure for holding the options set inline on a line, unset options use the global value
+#[derive(Debug, Clone, Default)]
+pub struct EntryOptions {
+    pub dir: Option<String>,
+    pub folder: Option<String>,
+    pub sfw: Option<bool>,
+    pub workers: Option<usize>,
+    pub tries: Option<usize>,
+    pub limit: Option<usize>,
+    pub order: Option<unit::Order>,
+    pub variant: Option<unit::Variant>,
+    pub min_score: Option<i64>,
+    pub sidecar: Option<bool>,
+    pub incremental: Option<bool>,
+    pub prune: Option<bool>,
+    pub export: Option<unit::Export>,
+    pub follow: Option<unit::Follow>,
+    pub follow_depth: Option<usize>,
+    pub commentary: Option<bool>,
+    pub wiki: Option<bool>,
+    pub notes: Option<bool>,
+    pub comments: Option<bool>,
+    pub sources: Option<bool>,
+}
+
+impl Entry {
+    /// Function that parses a line in the form `query | key=value | key=value`
+    pub fn parse(line: &str) -> Self {
+        let mut parts = line.split('|');
+        let query = String::from(parts.next().unwrap_or("").trim());
+        let mut options = EntryOptions::default();
+
+        for opt in parts {
+            let opt = opt.trim();
+            let (key, value) = match opt.find('=') {
+                Some(i) => (opt[..i].trim(), opt[i + 1..].trim()),
+                None => panic!("[-] Problem with tag file, option has no value: {}", opt),
+            };
+
+            match key {
+                "dir" => options.dir = Some(String::from(value)),
+                "folder" => options.folder = Some(String::from(value)),
+                "sfw" => options.sfw = Some(parse_option(key, value)),
+                "workers" => options.workers = Some(parse_option(key, value)),
+                "tries" => options.tries = Some(parse_option(key, value)),
+                "limit" => options.limit = Some(parse_option(key, value)),
+                "order" => options.order = Some(parse_option(key, value)),
+                "variant" => options.variant = Some(parse_option(key, value)),
+                "min_score" => options.min_score = Some(parse_option(key, value)),
+                "sidecar" => options.sidecar = Some(parse_option(key, value)),
+                "incremental" => options.incremental = Some(parse_option(key, value)),
+                "prune" => options.prune = Some(parse_option(key, value)),
+                "export" => options.export = Some(parse_option(key, value)),
+                "follow" => options.follow = Some(parse_option(key, value)),
+                "follow_depth" => options.follow_depth = Some(parse_option(key, value)),
+                "commentary" => options.commentary = Some(parse_option(key, value)),
+                "wiki" => options.wiki = Some(parse_option(key, value)),
+                "notes" => options.notes = Some(parse_option(key, value)),
+                "comments" => options.comments = Some(parse_option(key, value)),
+                "sources" => options.sources = Some(parse_option(key, value)),
+                e => panic!("[-] Problem with tag file, unknown option: {}", e),
+            }
+        }
+
+        Entry { query, options }
+    }
+}
+
+/// Function to parse the value of an inline option
+fn parse_option<T: std::str::FromStr>(key: &str, value: &str) -> T {
+    match value.parse::<T>() {
+        Ok(v) => v,
+        Err(_) => panic!(
+            "[-] Problem with tag file, invalid value for {}: {}",
+            key, value
+        ),
+    }
 }
 
 impl TagStore {
-    fn new() -> Self {
+    pub fn new() -> Self {
         TagStore {
             general: Vec::new(),
             pools: Vec::new(),
             single_posts: Vec::new(),
+            sets: Vec::new(),
+            groups: Vec::new(),
+        }
+    }
+
+    /// Function that adds every entry of another tag store to this one
+    pub fn extend(&mut self, other: TagStore) {
+        self.general.extend(other.general);
+        self.pools.extend(other.pools);
+        self.single_posts.extend(other.single_posts);
+        self.sets.extend(other.sets);
+        self.groups.extend(other.groups);
+    }
+
+    /// Function that moves every entry given as an e621 url into the list for that kind of url
+    pub fn resolve_links(&mut self) {
+        for entry in std::mem::take(&mut self.general) {
+            self.route(entry, |s| &mut s.general);
+        }
+        for entry in std::mem::take(&mut self.pools) {
+            self.route(entry, |s| &mut s.pools);
         }
+        for entry in std::mem::take(&mut self.single_posts) {
+            self.route(entry, |s| &mut s.single_posts);
+        }
+        for entry in std::mem::take(&mut self.sets) {
+            self.route(entry, |s| &mut s.sets);
+        }
+
+        for group in &mut self.groups {
+            for entry in &mut group.queries {
+                match link::parse(&entry.query) {
+                    Some(link::Link::Search(tags)) => entry.query = tags,
+                    Some(_) => panic!(
+                        "[-] Only search urls can be used in a group: {}",
+                        entry.query
+                    ),
+                    None => {}
+                }
+            }
+        }
+    }
+
+    /// Function that adds an entry to the list matching its url, or to its section if it isn't one
+    fn route(&mut self, mut entry: Entry, section: fn(&mut TagStore) -> &mut Vec<Entry>) {
+        let list = match link::parse(&entry.query) {
+            Some(link::Link::Post(id)) => {
+                entry.query = id.to_string();
+                &mut self.single_posts
+            }
+            Some(link::Link::Pool(id)) => {
+                entry.query = id.to_string();
+                &mut self.pools
+            }
+            Some(link::Link::Set(id)) => {
+                entry.query = id.to_string();
+                &mut self.sets
+            }
+            Some(link::Link::Search(tags)) => {
+                entry.query = tags;
+                &mut self.general
+            }
+            None => section(self),
+        };
+        list.push(entry);
     }
 }
 
@@ -28,17 +191,29 @@ pub fn read_tags(tag_file: Option<&str>) -> io::Result<TagStore> {
     let file = fs::File::open(tag_filepath)?;
     let reader = io::BufReader::new(file);
 
+    parse_tags(reader)
+}
+
+/// Function that parses queries from stdin, lines are treated as general queries unless a
+/// section header is given just like in the tags file
+pub fn read_stdin() -> io::Result<TagStore> {
+    let stdin = io::stdin();
+    parse_tags(stdin.lock())
+}
+
+/// Function that parses the tags file format from any reader
+fn parse_tags<R: BufRead>(reader: R) -> io::Result<TagStore> {
     let mut stor = TagStore::new();
     let mut last = &mut stor.general;
 
     for line in reader.lines() {
-        let line = line.unwrap();
+        let line = line?;
 
         if line.starts_with('#') {
             continue;
         }
 
-        if line.is_empty() {
+        if line.trim().is_empty() {
             continue;
         }
 
@@ -51,18 +226,34 @@ pub fn read_tags(tag_file: Option<&str>) -> io::Result<TagStore> {
                     last = &mut stor.pools;
                 }
                 "[single-post]" => last = &mut stor.single_posts,
+                "[sets]" => last = &mut stor.sets,
+                e if e.starts_with("[group:") && e.ends_with(']') => {
+                    let header = Entry::parse(&e[7..e.len() - 1]);
+                    stor.groups.push(Group {
+                        name: header.query,
+                        options: header.options,
+                        queries: Vec::new(),
+                    });
+                    last = &mut stor.groups.last_mut().unwrap().queries;
+                }
                 e => panic!("[-] Problem with tag file: {}", e),
             }
             continue;
         }
 
-        let line = line.trim();
-        last.push(String::from(line));
+        last.push(Entry::parse(&line));
     }
 
     Ok(stor)
 }
 
+/// Function to check whether there is a tags file in the current directory
+pub fn default_tag_file_exists() -> bool {
+    let mut tag_filepath = env::current_dir().expect("Couldn't get current directory path");
+    tag_filepath.push("tags");
+    tag_filepath.exists()
+}
+
 /// Function that handles finding or creating the tags file
 pub fn check_file_path(tag_file: Option<&str>) -> path::PathBuf {
     let mut tag_filepath = path::PathBuf::new();
@@ -72,7 +263,7 @@ pub fn check_file_path(tag_file: Option<&str>) -> path::PathBuf {
         tag_filepath.push("tags");
 
         if !tag_filepath.exists() {
-            let data = "# This file contains the tags and pools the program will download\n# Lines begginning with # are comments\n# Insert tags you wish to download in the appropriate group\n\n[general]\n\n[pools]\n\n[single-post]\n\n";
+            let data = "# This file contains the tags and pools the program will download\n# Lines begginning with # are comments\n# Insert tags you wish to download in the appropriate group\n\n[general]\n\n[pools]\n\n[single-post]\n\n[sets]\n\n# e621 urls for posts, pools, searches and sets can be used in any section\n# Options can be set per line, e.g. wolf | dir=foo | limit=500 | order=score | min_score=50 | sidecar=true\n# Queries in a group are merged into one folder, e.g. [group:canines]\n\n";
 
             fs::File::create("tags").expect("[-] Could not create tag file");
             fs::write(tag_filepath, data).expect("[-] Failed to write default config to tags file");
@@ -95,7 +286,12 @@ pub fn check_file_path(tag_file: Option<&str>) -> path::PathBuf {
 
 /// Function to check wheather any tags where retrived from the tags file if not tell the user to add some
 pub fn check_pop(tags: &TagStore) {
-    if tags.general.is_empty() && tags.pools.is_empty() && tags.single_posts.is_empty() {
+    if tags.general.is_empty()
+        && tags.pools.is_empty()
+        && tags.single_posts.is_empty()
+        && tags.sets.is_empty()
+        && tags.groups.iter().all(|g| g.queries.is_empty())
+    {
         println!("[-] Please add at least one tag to the tag file");
         std::process::exit(1);
     }
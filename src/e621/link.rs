@@ -0,0 +1,35 @@
+use url::Url;
+
+/// Enum for the kinds of e621 urls that can be given instead of a query or id
+#[derive(Debug, PartialEq)]
+pub enum Link {
+    Post(u64),
+    Pool(u64),
+    Set(u64),
+    Search(String),
+}
+
+/// Function that parses an e621 or e926 url copied from the browser
+pub fn parse(input: &str) -> Option<Link> {
+    let url = Url::parse(input).ok()?;
+
+    let host = url.host_str()?.trim_start_matches("www.");
+    if host != "e621.net" && host != "e926.net" {
+        return None;
+    }
+
+    let segments: Vec<&str> = url.path_segments()?.filter(|s| !s.is_empty()).collect();
+    let id = |s: &str| s.trim_end_matches(".json").parse::<u64>().ok();
+
+    match segments.as_slice() {
+        ["posts"] => url
+            .query_pairs()
+            .find(|(key, _)| key == "tags")
+            .map(|(_, tags)| Link::Search(tags.trim().to_string()))
+            .filter(|link| link != &Link::Search(String::new())),
+        ["posts", post_id] | ["post", "show", post_id] => id(post_id).map(Link::Post),
+        ["pools", pool_id] => id(pool_id).map(Link::Pool),
+        ["post_sets", set_id] => id(set_id).map(Link::Set),
+        _ => None,
+    }
+}
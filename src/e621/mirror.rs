@@ -0,0 +1,304 @@
+use crate::catalog;
+use crate::download;
+use crate::export;
+use crate::pool;
+use crate::scraper;
+use crate::unit;
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The directory deleted posts are moved into when they are taken out of the archive
+pub const DELETED_DIR: &str = "deleted";
+
+/// The directory the old version of replaced posts is moved into
+pub const REPLACED_DIR: &str = "replaced";
+
+/// The directory files of posts that no longer match their query are moved into
+pub const TRASH_DIR: &str = "trash";
+
+/// Function that compares the catalog of every directory in the archive with the current state
+/// of its posts, handling posts that have been deleted or replaced since they were downloaded
+pub async fn check(config: &unit::Config) {
+    println!("\n[=] Checking For Deleted And Replaced Posts");
+
+    let base = download::base_dir(config);
+    let app_client = scraper::client(config);
+
+    let mut deleted_total = 0;
+    let mut replaced_total = 0;
+
+    for dir in archive_dirs(&base) {
+        let mut cat = catalog::Catalog::load(&dir);
+        if cat.posts.is_empty() {
+            continue;
+        }
+
+        let folder = dir.strip_prefix(&base).unwrap().to_path_buf();
+        let ids: Vec<u64> = cat.posts.keys().copied().collect();
+
+        // Posts are looked up with the rendition they were downloaded as so the md5 and url match
+        let mut current = HashMap::new();
+        for variant in [
+            unit::Variant::Original,
+            unit::Variant::Sample,
+            unit::Variant::Preview,
+        ] {
+            let variant_ids: Vec<u64> = ids
+                .iter()
+                .copied()
+                .filter(|id| cat.posts[id].variant == variant)
+                .collect();
+            if variant_ids.is_empty() {
+                continue;
+            }
+
+            let mut variant_config = config.clone();
+            variant_config.variant = variant;
+            current.extend(scraper::lookup_posts(&variant_ids, &variant_config).await);
+        }
+
+        for id in ids {
+            let record = cat.posts[&id].clone();
+
+            match current.get(&id) {
+                // Posts can be missing because a request failed or e926 hides them, only posts
+                // e621 marks as deleted are treated as deleted
+                None if config.verbose => {
+                    println!("[!] Post {} wasn't returned, leaving it alone", id);
+                }
+                Some((_, true)) => {
+                    if record.deleted && !config.move_deleted {
+                        continue;
+                    }
+
+                    deleted_total += 1;
+                    println!(
+                        "[!] Deleted: post {} ({})",
+                        id,
+                        dir.join(&record.file).display()
+                    );
+
+                    if config.dry_run {
+                        continue;
+                    }
+
+                    if config.move_deleted {
+                        move_post(&dir, &record.file, &base.join(DELETED_DIR).join(&folder));
+                        cat.posts.remove(&id);
+                    } else {
+                        cat.posts.get_mut(&id).unwrap().deleted = true;
+                    }
+                }
+                Some((post, false)) if post.md5 != record.md5 => {
+                    replaced_total += 1;
+                    println!("[!] Replaced: post {} ({} -> {})", id, record.md5, post.md5);
+
+                    if config.dry_run {
+                        continue;
+                    }
+
+                    let url = match &post.url {
+                        Some(url) => url,
+                        None => {
+                            println!("[-] Skipping post {}: no file url", id);
+                            continue;
+                        }
+                    };
+
+                    let data = match fetch(&app_client, url, config.tries).await {
+                        Some(data) => data,
+                        None => {
+                            println!("[-] Failed to download replacement for post {}", id);
+                            continue;
+                        }
+                    };
+
+                    // Pool pages keep their page number, everything else is named after the md5
+                    let (stem, _) = record.file.rsplit_once('.').unwrap_or((&record.file, ""));
+                    let stem = stem.strip_suffix(record.variant.suffix()).unwrap_or(stem);
+                    let new_stem = if stem == record.md5 { &post.md5 } else { stem };
+                    let new_file = format!("{}{}.{}", new_stem, post.variant.suffix(), post.ext);
+
+                    // The new file is written under a temporary name first so the old one is only
+                    // moved once the replacement is safely on disk, pool pages can keep their name
+                    let part = dir.join(format!(".{}.part", new_file));
+                    fs::write(&part, data).expect("[-] Failed to write file");
+
+                    if config.keep_replaced {
+                        move_post(&dir, &record.file, &base.join(REPLACED_DIR).join(&folder));
+                    } else {
+                        fs::remove_file(dir.join(&record.file)).ok();
+                    }
+
+                    fs::rename(&part, dir.join(&new_file)).expect("[-] Failed to write file");
+
+                    cat.posts.insert(
+                        id,
+                        catalog::Record {
+                            file: new_file,
+                            md5: post.md5.clone(),
+                            variant: post.variant,
+                            deleted: false,
+                            parent: post.parent,
+                            children: post.children.clone(),
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        if !config.dry_run {
+            cat.save(&dir);
+        }
+    }
+
+    println!("[+] {} deleted, {} replaced", deleted_total, replaced_total);
+}
+
+/// Function that moves every file in the folder of a query that doesn't belong to one of the
+/// posts it currently returns into the trash directory, returning the amount of files moved
+pub fn prune(tag_dir: &str, len: usize, names: &HashSet<String>, config: &unit::Config) -> usize {
+    let base = download::base_dir(config);
+    let dir = download::target_dir(tag_dir, len, config);
+
+    // Files of single posts end up in the base directory which is shared by everything
+    if tag_dir.is_empty() || dir == base || !dir.is_dir() {
+        return 0;
+    }
+
+    // Posts left out by these are still valid files of the query
+    if config.limit.is_some() || config.min_score.is_some() || !config.blacklist.is_empty() {
+        println!(
+            "[!] Not pruning {}: limit, min_score and the blacklist leave posts out of the results",
+            tag_dir
+        );
+        return 0;
+    }
+
+    // An empty result is more likely a failed scrape than a query nothing matches anymore
+    if names.is_empty() {
+        println!("[!] Not pruning {}: the query returned no posts", tag_dir);
+        return 0;
+    }
+
+    let mut stale: Vec<String> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_file())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            // Sidecar files share the name of the post they belong to
+            .filter(|name| !name.starts_with('.'))
+            .filter(|name| !names.contains(name.split('.').next().unwrap()))
+            // Files describing the folder itself don't belong to a post
+            .filter(|name| name != pool::POOL_FILE && name != pool::README_FILE)
+            .collect(),
+        Err(_) => return 0,
+    };
+    stale.sort();
+
+    if stale.is_empty() {
+        return 0;
+    }
+
+    let trash = base.join(TRASH_DIR).join(tag_dir);
+    for name in &stale {
+        if config.verbose || config.dry_run {
+            println!("    {}", dir.join(name).display());
+        }
+
+        if !config.dry_run {
+            fs::create_dir_all(&trash).expect("[-] Failed to create trash directory");
+            fs::rename(dir.join(name), trash.join(name)).expect("[-] Failed to move file");
+        }
+    }
+
+    if !config.dry_run {
+        let mut cat = catalog::Catalog::load(&dir);
+        cat.posts.retain(|_, record| !stale.contains(&record.file));
+        cat.save(&dir);
+    }
+
+    println!(
+        "[+] {}: {} {} stale files to {}",
+        tag_dir,
+        if config.dry_run {
+            "would move"
+        } else {
+            "moved"
+        },
+        stale.len(),
+        trash.display()
+    );
+
+    stale.len()
+}
+
+/// Function that lists the base directory and every download directory in it, skipping the
+/// directories files get moved into
+fn archive_dirs(base: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![base.to_path_buf()];
+
+    if let Ok(entries) = fs::read_dir(base) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            let name = entry.file_name();
+
+            if path.is_dir()
+                && name != DELETED_DIR
+                && name != REPLACED_DIR
+                && name != TRASH_DIR
+                && name != export::EXPORT_DIR
+            {
+                dirs.push(path);
+            }
+        }
+    }
+
+    dirs.sort();
+    dirs
+}
+
+/// Function that moves a downloaded file and its sidecar files into another directory
+pub fn move_post(dir: &Path, file: &str, to: &Path) {
+    let (stem, _) = file.rsplit_once('.').unwrap_or((file, ""));
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        // Sidecar files share the name of the post they belong to
+        if name == file || name.starts_with(&format!("{}.", stem)) {
+            fs::create_dir_all(to).expect("[-] Failed to create directory");
+            fs::rename(entry.path(), to.join(&name)).expect("[-] Failed to move file");
+        }
+    }
+}
+
+/// Function that downloads a file a certain amount of times until it succeeds
+async fn fetch(client: &reqwest::Client, url: &str, tries: usize) -> Option<Vec<u8>> {
+    for _ in 0..tries {
+        scraper::throttle().await;
+        let response = match client.get(url).send().await {
+            Ok(response) => response,
+            Err(_) => continue,
+        };
+
+        // Error pages would otherwise be saved in place of the file
+        if !response.status().is_success() {
+            continue;
+        }
+
+        if let Ok(data) = response.bytes().await {
+            return Some(data.to_vec());
+        }
+    }
+
+    None
+}
@@ -1,4 +1,17 @@
+pub mod catalog;
+pub mod commands;
 pub mod download;
+pub mod dtext;
+pub mod export;
 pub mod file;
+pub mod link;
+pub mod mirror;
+pub mod notes;
+pub mod pool;
+pub mod preflight;
+pub mod query;
 pub mod scraper;
+pub mod settings;
+pub mod sources;
 pub mod unit;
+pub mod wiki;
@@ -0,0 +1,176 @@
+use crate::scraper;
+use crate::unit;
+
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// The most notes or comments e621 returns on a single page
+const PAGE_LIMIT: usize = 320;
+
+/// Function that saves the notes and comments of a post next to its file when they are wanted,
+/// posts that already have them saved aren't fetched again
+pub fn save(
+    post: &unit::Unit,
+    dir: &Path,
+    client: &reqwest::blocking::Client,
+    config: &unit::Config,
+) {
+    let id = post.post_id.to_string();
+
+    if config.notes {
+        let path = dir.join(format!("{}.notes.json", post.name));
+
+        let notes = if path.exists() {
+            // Notes saved by an earlier run are read back so the overlay can still be added later
+            fs::read_to_string(&path)
+                .ok()
+                .and_then(|data| serde_json::from_str::<Vec<Value>>(&data).ok())
+        } else {
+            fetch(client, "notes.json", &[("search[post_id]", &id)], config).map(|notes| {
+                // Notes that were removed are still returned by the api
+                let notes: Vec<Value> = notes
+                    .into_iter()
+                    .filter(|n| n["is_active"].as_bool().unwrap_or(true))
+                    .collect();
+
+                write(&path, &notes);
+                notes
+            })
+        };
+
+        let overlay_path = dir.join(format!("{}.notes.html", post.name));
+        if let Some(notes) = notes {
+            if config.note_overlay && !notes.is_empty() && !overlay_path.exists() {
+                fs::write(overlay_path, overlay(post, &notes))
+                    .expect("[-] Failed to write note overlay");
+            }
+        }
+    }
+
+    if config.comments {
+        let path = dir.join(format!("{}.comments.json", post.name));
+
+        if !path.exists() {
+            let params = [("group_by", "comment"), ("search[post_id]", id.as_str())];
+            if let Some(comments) = fetch(client, "comments.json", &params, config) {
+                write(&path, &comments);
+            }
+        }
+    }
+}
+
+/// Function that fetches a list from the api page by page until a page comes back short. Failed
+/// requests and error bodies return nothing so the post is tried again on the next run
+fn fetch(
+    client: &reqwest::blocking::Client,
+    endpoint: &str,
+    params: &[(&str, &str)],
+    config: &unit::Config,
+) -> Option<Vec<Value>> {
+    let mut list = Vec::new();
+    let limit = PAGE_LIMIT.to_string();
+
+    for page in 1.. {
+        let page = page.to_string();
+        let mut page_params = params.to_vec();
+        page_params.extend([("limit", limit.as_str()), ("page", page.as_str())]);
+
+        let batch = fetch_page(client, endpoint, &page_params, config)?;
+        let done = batch.len() < PAGE_LIMIT;
+        list.extend(batch);
+
+        if done {
+            break;
+        }
+    }
+
+    Some(list)
+}
+
+/// Function that fetches a single page of a list from the api, e621 returns an object holding an
+/// empty list instead of an empty list when nothing was found
+fn fetch_page(
+    client: &reqwest::blocking::Client,
+    endpoint: &str,
+    params: &[(&str, &str)],
+    config: &unit::Config,
+) -> Option<Vec<Value>> {
+    let url = scraper::api_url(endpoint, params, config);
+
+    scraper::throttle_blocking();
+    let response = client.get(&url).send().ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+
+    match response.json::<Value>().ok()? {
+        Value::Array(list) => Some(list),
+        Value::Object(map)
+            if map.len() == 1 && map.values().all(|v| v == &Value::Array(Vec::new())) =>
+        {
+            Some(Vec::new())
+        }
+        _ => None,
+    }
+}
+
+/// Function that writes a list as a json sidecar file
+fn write(path: &Path, list: &[Value]) {
+    fs::write(path, serde_json::to_string_pretty(list).unwrap())
+        .expect("[-] Failed to write sidecar file");
+}
+
+/// Function that builds a html page showing the notes of a post on top of its image, note
+/// positions are relative to the original so they line up with samples and previews too
+fn overlay(post: &unit::Unit, notes: &[Value]) -> String {
+    let percent =
+        |value: &Value, size: u64| value.as_f64().unwrap_or(0.0) / size.max(1) as f64 * 100.0;
+
+    let mut boxes = String::new();
+    for note in notes {
+        boxes.push_str(&format!(
+            "    <div class=\"note\" style=\"left: {:.3}%; top: {:.3}%; width: {:.3}%; height: {:.3}%\"><div class=\"body\">{}</div></div>\n",
+            percent(&note["x"], post.width),
+            percent(&note["y"], post.height),
+            percent(&note["width"], post.width),
+            percent(&note["height"], post.height),
+            escape_html(note["body"].as_str().unwrap_or(""))
+        ));
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>Notes for post {id}</title>
+  <style>
+    .post {{ position: relative; display: inline-block; }}
+    .post img {{ display: block; max-width: 100%; }}
+    .note {{ position: absolute; border: 1px solid #000; background: rgba(255, 255, 238, 0.5); }}
+    .note .body {{ display: none; position: absolute; top: 100%; left: 0; min-width: 12em; padding: 4px; background: #ffe; border: 1px solid #000; color: #000; z-index: 1; }}
+    .note:hover .body {{ display: block; }}
+  </style>
+</head>
+<body>
+  <div class="post">
+    <img src="{file}" alt="Post {id}">
+{boxes}  </div>
+</body>
+</html>
+"#,
+        id = post.post_id,
+        file = post.file_name(),
+        boxes = boxes
+    )
+}
+
+/// Function that escapes text so it is shown as it is in html, line breaks are kept
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\n', "<br>")
+}
@@ -0,0 +1,346 @@
+use crate::catalog;
+use crate::download;
+use crate::dtext;
+use crate::mirror;
+use crate::unit;
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// The file the full metadata of a pool is saved in
+pub const POOL_FILE: &str = "pool.json";
+
+/// The file a readable summary of a pool is saved in
+pub const README_FILE: &str = "README.txt";
+
+/// Function that moves the folder of a pool to its current name when the pool was renamed or the
+/// folder template changed, folders are matched through the pool.json written into them
+pub fn find_folder(container: &unit::Container, config: &unit::Config) {
+    let pool = match &container.pool {
+        Some(pool) => pool,
+        None => return,
+    };
+
+    let base = download::base_dir(config);
+    let target = base.join(&container.tag_name);
+    if target.exists() {
+        return;
+    }
+
+    let entries = match fs::read_dir(&base) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+
+        let found = match fs::read_to_string(path.join(POOL_FILE)) {
+            Ok(data) => serde_json::from_str::<Value>(&data).is_ok_and(|p| p["id"] == pool["id"]),
+            // Folders downloaded before pool.json was written are named after the pool, which
+            // isn't unique so the folder also has to hold posts of it
+            Err(_) => {
+                pool["name"].as_str() == entry.file_name().to_str()
+                    && holds_pool(&path, pool, container)
+            }
+        };
+
+        if found {
+            println!(
+                "[+] Moving the folder of pool {}: {} -> {}",
+                pool["id"],
+                entry.file_name().to_string_lossy(),
+                container.tag_name
+            );
+
+            if !config.dry_run {
+                fs::rename(&path, &target).expect("[-] Failed to move pool folder");
+            }
+            return;
+        }
+    }
+}
+
+/// Function to check whether a folder holds posts of a pool, either through its catalog or by
+/// the md5 of the files in it
+fn holds_pool(dir: &Path, pool: &Value, container: &unit::Container) -> bool {
+    let cat = catalog::Catalog::load(dir);
+    let in_catalog = pool["post_ids"].as_array().is_some_and(|ids| {
+        ids.iter()
+            .filter_map(|id| id.as_u64())
+            .any(|id| cat.posts.contains_key(&id))
+    });
+    if in_catalog {
+        return true;
+    }
+
+    let md5s: Vec<&str> = container.queue.iter().map(|u| u.md5.as_str()).collect();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+
+    entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file())
+        .filter_map(|e| fs::read(e.path()).ok())
+        .any(|data| md5s.contains(&format!("{:x}", md5::compute(data)).as_str()))
+}
+
+/// Function that renames the downloaded pages of a pool to match the current order of its posts,
+/// pages are matched to their post through the catalog of the pool folder
+pub fn reorder(container: &unit::Container, config: &unit::Config) {
+    // Pools with a single page are downloaded into the base directory
+    if container.queue.len() < 2 {
+        return;
+    }
+
+    let dir = download::base_dir(config).join(&container.tag_name);
+    if !dir.is_dir() {
+        return;
+    }
+
+    let mut cat = catalog::Catalog::load(&dir);
+    let pages: HashMap<u64, &unit::Unit> = container.queue.iter().map(|u| (u.post_id, u)).collect();
+
+    // Pools downloaded before the catalog existed have their pages matched by md5
+    if cat.posts.is_empty() {
+        seed_catalog(&mut cat, &dir, container);
+
+        if cat.posts.is_empty() {
+            println!(
+                "[!] Pool {} has no catalog and its pages couldn't be matched to posts, not reordering",
+                container.tag_name
+            );
+            return;
+        }
+    }
+
+    // Posts that were taken out of the pool are moved to the trash so they don't block the page
+    // that took their place
+    let removed: Vec<u64> = cat
+        .posts
+        .iter()
+        .filter(|(post_id, record)| !pages.contains_key(post_id) && dir.join(&record.file).exists())
+        .map(|(post_id, _)| *post_id)
+        .collect();
+
+    // Every page that moved along with the name it should have now
+    let mut moves = Vec::new();
+    for (post_id, record) in &cat.posts {
+        let unit = match pages.get(post_id) {
+            Some(unit) => unit,
+            None => continue,
+        };
+        let (stem, ext) = split_name(&record.file);
+
+        if stem != unit.name && dir.join(&record.file).exists() {
+            moves.push((
+                *post_id,
+                stem.to_string(),
+                unit.name.clone(),
+                ext.to_string(),
+            ));
+        }
+    }
+
+    let trash = download::base_dir(config)
+        .join(mirror::TRASH_DIR)
+        .join(&container.tag_name);
+    for post_id in &removed {
+        println!(
+            "[!] Post {} was taken out of pool {}, moving {} to {}",
+            post_id,
+            container.tag_name,
+            cat.posts[post_id].file,
+            trash.display()
+        );
+
+        if !config.dry_run {
+            mirror::move_post(&dir, &cat.posts[post_id].file, &trash);
+            cat.posts.remove(post_id);
+        }
+    }
+
+    if moves.is_empty() {
+        if !config.dry_run {
+            cat.save(&dir);
+        }
+        return;
+    }
+
+    println!(
+        "[!] Pool {} has changed, renaming {} pages",
+        container.tag_name,
+        moves.len()
+    );
+    for (post_id, stem, new_stem, ext) in &moves {
+        if config.verbose || config.dry_run {
+            println!(
+                "    post {}: {}.{} -> {}.{}",
+                post_id, stem, ext, new_stem, ext
+            );
+        }
+    }
+
+    if config.dry_run {
+        return;
+    }
+
+    // Pages can swap places so they are all moved out of the way first
+    for (post_id, stem, _, _) in &moves {
+        rename_page(&dir, stem, &format!(".reorder-{}", post_id));
+    }
+
+    for (post_id, _, new_stem, ext) in &moves {
+        rename_page(&dir, &format!(".reorder-{}", post_id), new_stem);
+        cat.posts.get_mut(post_id).unwrap().file = format!("{}.{}", new_stem, ext);
+    }
+
+    cat.save(&dir);
+}
+
+/// Function that writes the metadata of a pool along with a readable summary into its folder
+pub fn write_info(pool: &Value, tag_dir: &str, len: usize, config: &unit::Config) {
+    // Pools with a single page are downloaded into the base directory
+    if len < 2 {
+        return;
+    }
+
+    let dir = download::base_dir(config).join(tag_dir);
+    let cat = catalog::Catalog::load(&dir);
+
+    let pages: Vec<Value> = pool["post_ids"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+        .map(|(page, post_id)| {
+            let file = post_id
+                .as_u64()
+                .and_then(|id| cat.posts.get(&id))
+                .map(|r| r.file.clone());
+            json!({ "page": page, "post_id": post_id, "file": file })
+        })
+        .collect();
+
+    let mut meta = pool.clone();
+    meta["pages"] = Value::from(pages.clone());
+    fs::write(
+        dir.join(POOL_FILE),
+        serde_json::to_string_pretty(&meta).unwrap(),
+    )
+    .expect("[-] Failed to write pool metadata");
+
+    let text = |key: &str| match &pool[key] {
+        Value::String(s) => s.clone(),
+        Value::Null => String::from("none"),
+        v => v.to_string(),
+    };
+
+    let title = text("name").replace('_', " ");
+    let mut readme = format!("{}\n{}\n\n", title, "=".repeat(title.chars().count()));
+    readme.push_str(&format!("Pool: https://e621.net/pools/{}\n", text("id")));
+    readme.push_str(&format!("Category: {}\n", text("category")));
+    readme.push_str(&format!("Creator: {}\n", text("creator_name")));
+    readme.push_str(&format!("Active: {}\n", text("is_active")));
+    readme.push_str(&format!("Updated: {}\n", text("updated_at")));
+
+    let description = dtext::to_markdown(pool["description"].as_str().unwrap_or(""));
+    if !description.is_empty() {
+        readme.push_str(&format!("\nDescription\n-----------\n{}\n", description));
+    }
+
+    readme.push_str("\nPages\n-----\n");
+    for page in &pages {
+        readme.push_str(&format!(
+            "{}: https://e621.net/posts/{} {}\n",
+            page["page"],
+            page["post_id"],
+            page["file"].as_str().unwrap_or("(missing)")
+        ));
+    }
+
+    fs::write(dir.join(README_FILE), readme).expect("[-] Failed to write pool readme");
+}
+
+/// Function that fills an empty catalog by matching the files in a pool folder to the posts of the
+/// pool through their md5, only originals can be matched this way
+fn seed_catalog(cat: &mut catalog::Catalog, dir: &Path, container: &unit::Container) {
+    let by_md5: HashMap<&str, &unit::Unit> = container
+        .queue
+        .iter()
+        .map(|u| (u.md5.as_str(), u))
+        .collect();
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') || !entry.path().is_file() {
+            continue;
+        }
+
+        let data = match fs::read(entry.path()) {
+            Ok(data) => data,
+            Err(_) => continue,
+        };
+
+        let md5 = format!("{:x}", md5::compute(data));
+        if let Some(unit) = by_md5.get(md5.as_str()) {
+            cat.posts.insert(
+                unit.post_id,
+                catalog::Record {
+                    file: name,
+                    md5,
+                    variant: unit::Variant::Original,
+                    deleted: false,
+                    parent: unit.parent,
+                    children: unit.children.clone(),
+                },
+            );
+        }
+    }
+}
+
+/// Function that renames a page along with its sidecar files
+fn rename_page(dir: &Path, stem: &str, new_stem: &str) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if let Some(rest) = name.strip_prefix(&format!("{}.", stem)) {
+            let new_path = dir.join(format!("{}.{}", new_stem, rest));
+            fs::rename(entry.path(), &new_path).expect("[-] Failed to rename page");
+
+            // Note overlays point at the image of their page by name
+            if rest == "notes.html" {
+                if let Ok(html) = fs::read_to_string(&new_path) {
+                    let html = html.replace(
+                        &format!("<img src=\"{}.", stem),
+                        &format!("<img src=\"{}.", new_stem),
+                    );
+                    fs::write(&new_path, html).expect("[-] Failed to rename page");
+                }
+            }
+        }
+    }
+}
+
+/// Function that splits a file name into the name of the post and its extension
+fn split_name(file: &str) -> (&str, &str) {
+    file.split_once('.').unwrap_or((file, ""))
+}
@@ -0,0 +1,150 @@
+use crate::file;
+use crate::query;
+use crate::scraper;
+use crate::unit;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Struct that represents a tag alias
+#[derive(Deserialize, Debug)]
+struct TagAlias {
+    antecedent_name: String,
+    consequent_name: String,
+}
+
+/// Struct that represents a tag implication
+#[derive(Deserialize, Debug)]
+struct TagImplication {
+    antecedent_name: String,
+    consequent_name: String,
+}
+
+/// Function that checks every query for aliased tags and empty results before scraping starts
+///
+/// Aliased tags are either reported or rewritten to their consequent depending on the config,
+/// queries that match zero posts are reported and removed so they aren't scraped
+pub async fn check_tags(tags: &mut Vec<file::Entry>, config: &unit::Config) {
+    println!("[=] Checking Tags");
+
+    let app_client = scraper::client(config);
+
+    let mut checked = Vec::new();
+
+    for mut entry in tags.drain(..) {
+        let config = &config.with_options(&entry.options);
+        let mut terms = Vec::new();
+
+        for term in entry.query.split_whitespace() {
+            let (prefix, name) = split_prefix(term);
+
+            // Metatags and wildcards can't be aliased so send them as is
+            if name.contains(':') || name.contains('*') {
+                terms.push(String::from(term));
+                continue;
+            }
+
+            let url = scraper::api_url(
+                "tag_aliases.json",
+                &[
+                    ("search[antecedent_name]", name),
+                    ("search[status]", "active"),
+                ],
+                config,
+            );
+            let aliases = get_json_list::<TagAlias>(&url, &app_client).await;
+
+            let name = match aliases.first() {
+                Some(alias) if config.rewrite_aliases => {
+                    println!(
+                        "[!] Rewrote aliased tag {} -> {}",
+                        alias.antecedent_name, alias.consequent_name
+                    );
+                    alias.consequent_name.clone()
+                }
+                Some(alias) => {
+                    println!(
+                        "[!] Tag {} is an alias of {}, use --rewrite-aliases to replace it",
+                        alias.antecedent_name, alias.consequent_name
+                    );
+                    String::from(name)
+                }
+                None => String::from(name),
+            };
+
+            if config.verbose {
+                let url = scraper::api_url(
+                    "tag_implications.json",
+                    &[
+                        ("search[antecedent_name]", &name),
+                        ("search[status]", "active"),
+                    ],
+                    config,
+                );
+
+                for imp in get_json_list::<TagImplication>(&url, &app_client).await {
+                    println!(
+                        "[=] Tag {} implies {}",
+                        imp.antecedent_name, imp.consequent_name
+                    );
+                }
+            }
+
+            terms.push(format!("{}{}", prefix, name));
+        }
+
+        let query = terms.join(" ");
+
+        // Only the part of the query e621 accepts can be checked for results
+        let server = query::Query::parse(&query).server;
+        let url = scraper::api_url("posts.json", &[("limit", "1"), ("tags", &server)], config);
+        scraper::throttle().await;
+        let posts = app_client
+            .get(&url)
+            .send()
+            .await
+            .unwrap()
+            .json::<Value>()
+            .await
+            .unwrap();
+
+        let empty = posts["posts"].as_array().is_none_or(|p| p.is_empty());
+        if empty {
+            println!("[-] Query matches zero posts, skipping: {}", query);
+            continue;
+        }
+
+        entry.query = query;
+        checked.push(entry);
+    }
+
+    *tags = checked;
+}
+
+/// Function that splits the `-` or `~` prefix off of a query term
+fn split_prefix(term: &str) -> (&str, &str) {
+    if term.starts_with('-') || term.starts_with('~') {
+        term.split_at(1)
+    } else {
+        ("", term)
+    }
+}
+
+/// Function used to fetch list endpoints, e621 returns an object instead of an array when empty
+async fn get_json_list<T: DeserializeOwned>(url: &str, client: &reqwest::Client) -> Vec<T> {
+    scraper::throttle().await;
+    let value = client
+        .get(url)
+        .send()
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+
+    match value {
+        Value::Array(_) => serde_json::from_value(value).unwrap(),
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,154 @@
+/// A single tag term that gets checked against a post locally
+#[derive(Debug, Clone)]
+enum Term {
+    Include(String),
+    Exclude(String),
+}
+
+/// Struct for holding a query split into the part sent to e621 and the part checked locally
+///
+/// e621 rejects searches with more than `MAX_SERVER_TAGS` tags, anything that doesn't fit is
+/// checked against the tags of each post once it has been fetched
+#[derive(Debug, Clone)]
+pub struct Query {
+    pub server: String,
+    terms: Vec<Term>,
+    any: Vec<String>,
+}
+
+impl Query {
+    /// Function that splits a query into a server side query and a set of local filters
+    pub fn parse(query: &str) -> Self {
+        let mut metatags = Vec::new();
+        let mut include = Vec::new();
+        let mut exclude = Vec::new();
+        let mut any = Vec::new();
+
+        // Tags on e621 are lowercase, terms are lowercased so the local filters still match posts
+        // when a query is typed with capitals
+        let lowered = query.to_lowercase();
+
+        for term in lowered.split_whitespace() {
+            if term.contains(':') {
+                metatags.push(term);
+            } else if let Some(tag) = term.strip_prefix('-') {
+                exclude.push(tag);
+            } else if let Some(tag) = term.strip_prefix('~') {
+                any.push(tag);
+            } else {
+                include.push(term);
+            }
+        }
+
+        // Metatags can't be checked locally so they always get sent
+        let mut server: Vec<String> = metatags.iter().map(|t| String::from(*t)).collect();
+        if server.len() > crate::MAX_SERVER_TAGS {
+            println!(
+                "[!] Query has more than {} metatags, e621 may reject it: {}",
+                crate::MAX_SERVER_TAGS,
+                query
+            );
+        }
+
+        let mut local = Query {
+            server: String::new(),
+            terms: Vec::new(),
+            any: Vec::new(),
+        };
+
+        for tag in include {
+            if server.len() < crate::MAX_SERVER_TAGS {
+                server.push(String::from(tag));
+            } else {
+                local.terms.push(Term::Include(String::from(tag)));
+            }
+        }
+
+        // Or groups have to be sent all together or not at all
+        if server.len() + any.len() <= crate::MAX_SERVER_TAGS {
+            server.extend(any.iter().map(|t| format!("~{}", t)));
+        } else {
+            local.any = any.iter().map(|t| String::from(*t)).collect();
+        }
+
+        for tag in exclude {
+            if server.len() < crate::MAX_SERVER_TAGS {
+                server.push(format!("-{}", tag));
+            } else {
+                local.terms.push(Term::Exclude(String::from(tag)));
+            }
+        }
+
+        local.server = server.join(" ");
+        local
+    }
+
+    /// Function to check whether any terms have to be checked locally
+    pub fn is_split(&self) -> bool {
+        !self.terms.is_empty() || !self.any.is_empty()
+    }
+
+    /// Function that checks the local part of the query against the tags of a post
+    pub fn matches(&self, tags: &[&str]) -> bool {
+        let has = |pattern: &str| tags.iter().any(|t| glob_match(pattern, t));
+
+        let terms = self.terms.iter().all(|term| match term {
+            Term::Include(tag) => has(tag),
+            Term::Exclude(tag) => !has(tag),
+        });
+
+        terms && (self.any.is_empty() || self.any.iter().any(|tag| has(tag)))
+    }
+}
+
+/// Function that lists the terms of a query that name a single tag, leaving out metatags,
+/// excluded tags and wildcards
+pub fn plain_tags(query: &str) -> Vec<&str> {
+    query
+        .split_whitespace()
+        .map(|term| term.trim_start_matches('~'))
+        .filter(|term| !term.contains(':') && !term.starts_with('-') && !term.contains('*'))
+        .filter(|term| !term.is_empty())
+        .collect()
+}
+
+/// Function to check whether a query contains a metatag with the given name, like `order:`
+pub fn has_metatag(query: &str, name: &str) -> bool {
+    query.split_whitespace().any(|term| {
+        term.split_once(':')
+            .is_some_and(|(key, _)| key.eq_ignore_ascii_case(name))
+    })
+}
+
+/// Function to check whether any of the tags match any of the patterns
+pub fn matches_any(patterns: &[String], tags: &[&str]) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| tags.iter().any(|t| glob_match(pattern, t)))
+}
+
+/// Function that matches a tag against a pattern that may contain `*` wildcards
+fn glob_match(pattern: &str, tag: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+
+    if !tag.starts_with(first) {
+        return false;
+    }
+
+    let mut rest = &tag[first.len()..];
+    let parts: Vec<&str> = parts.collect();
+
+    match parts.split_last() {
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.ends_with(last)
+        }
+    }
+}
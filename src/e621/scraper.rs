@@ -1,11 +1,35 @@
+use crate::catalog;
+use crate::download;
+use crate::file;
+use crate::mirror;
+use crate::query;
 use crate::unit;
 
-use serde::Deserialize;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use once_cell::sync::Lazy;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use url::Url;
 
+/// e621 doesn't serve numbered pages past this one
+const MAX_PAGE: u64 = 750;
+
+/// How many posts in a row have to be downloaded already before an incremental walk stops
+const KNOWN_RUN: usize = 20;
+
+/// e621 asks for no more than two api requests a second
+const REQUEST_GAP: Duration = Duration::from_millis(500);
+
+/// The time the next api request is allowed to go out, shared by the scraper and the workers
+static NEXT_REQUEST: Lazy<Mutex<Instant>> = Lazy::new(|| Mutex::new(Instant::now()));
+
 /// Struct used for deserializing tag results
 #[derive(Deserialize, Debug)]
 struct TagPayload {
@@ -13,7 +37,7 @@ struct TagPayload {
 }
 
 /// Struct that represents a post
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 struct Post {
     id: u64,
     file: File,
@@ -23,16 +47,219 @@ struct Post {
 }
 
 /// Struct that represents a file
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 struct File {
     ext: String,
     md5: String,
     url: Option<String>,
+    #[serde(default)]
+    size: u64,
 
     #[serde(flatten)]
     extra: HashMap<String, Value>,
 }
 
+impl Post {
+    /// Function that collects the tags of every category on a post
+    fn tags(&self) -> Vec<&str> {
+        let mut tags = Vec::new();
+
+        if let Some(Value::Object(categories)) = self.extra.get("tags") {
+            for list in categories.values() {
+                if let Value::Array(list) = list {
+                    tags.extend(list.iter().filter_map(|t| t.as_str()));
+                }
+            }
+        }
+
+        tags
+    }
+
+    /// Function that gets the artist tags of a post
+    fn artists(&self) -> Vec<String> {
+        self.extra
+            .get("tags")
+            .and_then(|t| t["artist"].as_array())
+            .map(|a| {
+                a.iter()
+                    .filter_map(|t| t.as_str())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Function that gets the total score of a post
+    fn score(&self) -> i64 {
+        self.extra
+            .get("score")
+            .and_then(|s| s["total"].as_i64())
+            .unwrap_or(0)
+    }
+
+    /// Function that gets the url of the rendition selected in the config, falling back to the
+    /// original file when the post doesn't have that rendition
+    fn variant_url(&self, config: &unit::Config) -> (Option<&str>, unit::Variant) {
+        let rendition = match config.variant {
+            unit::Variant::Original => None,
+            unit::Variant::Sample => self
+                .extra
+                .get("sample")
+                .filter(|s| s["has"].as_bool().unwrap_or(false)),
+            unit::Variant::Preview => self.extra.get("preview"),
+        };
+
+        match rendition.and_then(|r| r["url"].as_str()) {
+            Some(url) => (Some(url), config.variant),
+            None => (self.file.url.as_deref(), unit::Variant::Original),
+        }
+    }
+
+    /// Function that rebuilds the url of a post from its md5, the api leaves it out for posts
+    /// hidden from anonymous users but the files are still stored at a predictable path
+    fn static_url(&self, config: &unit::Config) -> (Option<String>, unit::Variant) {
+        let md5 = &self.file.md5;
+        if md5.len() < 4 {
+            return (None, unit::Variant::Original);
+        }
+
+        let has_sample = self
+            .extra
+            .get("sample")
+            .and_then(|s| s["has"].as_bool())
+            .unwrap_or(false);
+
+        let (dir, ext, variant) = match config.variant {
+            unit::Variant::Sample if has_sample => ("sample/", "jpg", unit::Variant::Sample),
+            unit::Variant::Preview => ("preview/", "jpg", unit::Variant::Preview),
+            _ => ("", self.file.ext.as_str(), unit::Variant::Original),
+        };
+
+        let url = format!(
+            "https://static1.e621.net/data/{}{}/{}/{}.{}",
+            dir,
+            &md5[0..2],
+            &md5[2..4],
+            md5,
+            ext
+        );
+
+        (Some(url), variant)
+    }
+
+    /// Function that turns a post into a Unit to be downloaded under the given name
+    fn to_unit(&self, name: String, config: &unit::Config) -> unit::Unit {
+        let (url, variant) = match self.variant_url(config) {
+            (Some(url), variant) => (Some(String::from(url)), variant),
+            (None, _) if config.reconstruct_urls => self.static_url(config),
+            (None, variant) => (None, variant),
+        };
+
+        // Samples and previews are usually jpgs so the extension has to come from the url
+        let ext = url
+            .as_deref()
+            .and_then(|u| u.rsplit('/').next())
+            .and_then(|f| f.rsplit_once('.'))
+            .map_or(self.file.ext.clone(), |(_, ext)| String::from(ext));
+
+        // The api only gives the size of the original
+        let size = match variant {
+            unit::Variant::Original => self.file.size,
+            _ => 0,
+        };
+
+        // Note positions are relative to the original whatever rendition is downloaded
+        let dimension = |key: &str| {
+            self.file
+                .extra
+                .get(key)
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0)
+        };
+
+        let (parent, children) = self.relationships();
+
+        unit::Unit {
+            post_id: self.id,
+            name,
+            md5: self.file.md5.clone(),
+            ext,
+            url,
+            size,
+            width: dimension("width"),
+            height: dimension("height"),
+            variant,
+            artists: self.artists(),
+            commentary: self.commentary(config),
+            parent,
+            children,
+            sources: self.sources(),
+            meta: self.meta(config),
+        }
+    }
+
+    /// Function that gets the parent and the children of a post
+    fn relationships(&self) -> (Option<u64>, Vec<u64>) {
+        let relationships = match self.extra.get("relationships") {
+            Some(r) => r,
+            None => return (None, Vec::new()),
+        };
+
+        let children = relationships["children"]
+            .as_array()
+            .map(|c| c.iter().filter_map(|id| id.as_u64()).collect())
+            .unwrap_or_default();
+
+        (relationships["parent_id"].as_u64(), children)
+    }
+
+    /// Function that gets the source urls of a post
+    fn sources(&self) -> Vec<String> {
+        self.extra
+            .get("sources")
+            .and_then(|s| s.as_array())
+            .map(|s| {
+                s.iter()
+                    .filter_map(|url| url.as_str())
+                    .map(|url| url.trim())
+                    .filter(|url| !url.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Function that checks whether a post has been deleted
+    fn is_deleted(&self) -> bool {
+        self.extra
+            .get("flags")
+            .and_then(|f| f["deleted"].as_bool())
+            .unwrap_or(false)
+    }
+
+    /// Function that gets the description the artist wrote for a post when it is wanted
+    fn commentary(&self, config: &unit::Config) -> Option<String> {
+        if !config.commentary {
+            return None;
+        }
+
+        self.extra
+            .get("description")
+            .and_then(|d| d.as_str())
+            .filter(|d| !d.trim().is_empty())
+            .map(String::from)
+    }
+
+    /// Function that keeps the full post metadata around when sidecar files are wanted
+    fn meta(&self, config: &unit::Config) -> Option<Value> {
+        if config.sidecar {
+            serde_json::to_value(self).ok()
+        } else {
+            None
+        }
+    }
+}
+
 impl Iterator for TagPayload {
     type Item = Post;
 
@@ -42,7 +269,7 @@ impl Iterator for TagPayload {
 }
 
 /// Struct that represents a pool
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 struct Pool {
     id: u64,
     name: String,
@@ -52,248 +279,642 @@ struct Pool {
     extra: HashMap<String, Value>,
 }
 
+impl Pool {
+    /// Function that names the folder of a pool after the template in the config
+    fn folder(&self, config: &unit::Config) -> String {
+        let category = self
+            .extra
+            .get("category")
+            .and_then(|c| c.as_str())
+            .unwrap_or("");
+
+        // Pool names use underscores instead of spaces like tags do
+        download::sanitize(
+            &config
+                .pool_folder
+                .replace("{id}", &self.id.to_string())
+                .replace("{name}", &self.name.replace('_', " "))
+                .replace("{category}", category),
+        )
+    }
+}
+
+/// Struct that represents a post set
+#[derive(Deserialize, Debug)]
+struct PostSet {
+    name: String,
+    shortname: String,
+}
+
 /// Function to build a queue full of untis for a specfifed tag
 pub async fn build_tag_queue(tag: &str, config: &unit::Config) -> unit::Container {
+    build_tag_queue_in(tag, tag, config).await
+}
+
+/// Function to build a queue for a tag that gets downloaded into the folder of another name,
+/// the folder is used to find posts that were already downloaded by earlier runs
+pub async fn build_tag_queue_in(tag: &str, folder: &str, config: &unit::Config) -> unit::Container {
     let mut queue = VecDeque::new();
+    let folder = download::sanitize(folder);
+
+    // Walking newest first means everything after a run of known posts was seen by an earlier run,
+    // pruning needs the whole result set and a limit or another order would pick other posts than
+    // a full walk, so those never stop early
+    let stop_early = config.incremental
+        && !config.prune
+        && config.limit.is_none()
+        && config.order == unit::Order::Oldest;
+    let order = if stop_early {
+        unit::Order::Newest
+    } else {
+        config.order
+    };
+    let known_dir = download::existing_dir(&folder, config);
+    let known = if stop_early {
+        catalog::Catalog::load(&known_dir)
+    } else {
+        catalog::Catalog::default()
+    };
+
+    // Only posts up to the newest one of a finished run are trusted, files of a run that got
+    // interrupted can sit in front of older posts it never reached
+    let synced = known.synced.get(tag).copied().unwrap_or(0);
+    let mut newest: Option<u64> = None;
+    let mut complete = config.limit.is_none();
 
     // Create the client used for downloading
-    let app_client = reqwest::Client::builder()
-        .user_agent(crate::APP_USER_AGENT)
-        .build()
-        .unwrap();
+    let app_client = client(config);
 
-    let mut head = 0;
+    let mut head: Option<u64> = None;
+    let mut page = 1;
+    let mut known_run = 0;
+    let mut seen = HashSet::new();
 
     println!("[+] Scraping Tag: {}", tag);
 
+    // Ordering by score is done by e621 so it has to be part of the query, queries that already
+    // pick an order keep their own since e621 can't sort by two
+    let full_query = match order {
+        unit::Order::Score if !query::has_metatag(tag, "order") => format!("{} order:score", tag),
+        _ => String::from(tag),
+    };
+
+    // Only part of a long query can be sent to e621, the rest is checked here
+    let query = query::Query::parse(&full_query);
+    if query.is_split() && config.verbose {
+        println!("[!] Query too long, sending: {}", query.server);
+    }
+
+    let limit = config.limit.unwrap_or(usize::MAX);
+
     // e621 uses relative tag_id's we can walk the entire contents of a tag
-    // by using the last id on the page as the starting id for the next page
-    loop {
-        let a_str = format!("a{}", head.to_string());
-
-        let mut url = urlencoding::decode(
-            Url::parse_with_params(
-                "https://e621.net/posts.json",
-                &[("limit", "320"), ("tags", tag), ("page", &a_str)],
-            )
-            .unwrap()
-            .as_str(),
-        )
-        .unwrap();
-        if config.sfw {
-            url = urlencoding::decode(
-                Url::parse_with_params(
-                    "https:///e926.net/posts.json",
-                    &[("limit", "320"), ("tags", tag), ("page", &a_str)],
-                )
-                .unwrap()
-                .as_str(),
-            )
-            .unwrap();
-        }
+    // by using the last id on the page as the starting id for the next page,
+    // upwards with a{id} for the oldest posts first or downwards with b{id} for the newest
+    while queue.len() < limit {
+        let page_str = match order {
+            unit::Order::Oldest => format!("a{}", head.unwrap_or(0)),
+            unit::Order::Newest => head.map_or(String::from("1"), |h| format!("b{}", h)),
+            unit::Order::Score => page.to_string(),
+        };
+
+        let url = api_url(
+            "posts.json",
+            &[
+                ("limit", "320"),
+                ("tags", &query.server),
+                ("page", &page_str),
+            ],
+            config,
+        );
 
-        let mut batch = get_tag_json(&url, &app_client).await;
+        let batch = get_tag_json(&url, &app_client).await;
 
         if batch.posts.is_empty() {
             break;
         }
 
-        head = batch.posts.get(0).unwrap().id;
-
-        for post in &mut batch {
-            queue.push_back(unit::Unit {
-                dir_tag: String::from(tag),
-                name: post.file.md5,
-                ext: post.file.ext,
-                url: post.file.url,
-            });
+        // Pages are sorted newest first, the oldest walk has to flip them around
+        let posts: Vec<Post> = match order {
+            unit::Order::Oldest => {
+                head = Some(batch.posts.first().unwrap().id);
+                batch.collect()
+            }
+            unit::Order::Newest => {
+                head = Some(batch.posts.last().unwrap().id);
+                batch.posts
+            }
+            unit::Order::Score => {
+                page += 1;
+                batch.posts
+            }
+        };
+
+        let mut reached_known = false;
+
+        for post in posts {
+            if queue.len() >= limit {
+                break;
+            }
+
+            newest = newest.max(Some(post.id));
+
+            // Scores change while paging so the same post can show up twice
+            if !seen.insert(post.id) {
+                continue;
+            }
+
+            // Filtered posts never get downloaded so they don't break a run of known posts
+            let tags = post.tags();
+            if !query.matches(&tags) || query::matches_any(&config.blacklist, &tags) {
+                continue;
+            }
+
+            if config.min_score.is_some_and(|min| post.score() < min) {
+                continue;
+            }
+
+            // Posts only count as known while their file is still on disk, files can go missing
+            // so a single known post isn't enough to stop
+            if stop_early {
+                let on_disk = post.id <= synced
+                    && (known.contains(&known_dir, post.id)
+                        || known_dir
+                            .join(format!("{}.{}", post.file.md5, post.file.ext))
+                            .exists());
+
+                known_run = if on_disk { known_run + 1 } else { 0 };
+                if known_run >= KNOWN_RUN {
+                    reached_known = true;
+                    break;
+                }
+            }
+
+            queue.push_back(post.to_unit(post.file.md5.clone(), config));
         }
 
         if config.verbose {
-            println!("Head: {}", head);
+            println!("Page: {}", page_str);
             println!("tag: {}", tag);
             println!("{}", url);
             println!("Size: {}\n", queue.len());
         }
+
+        if reached_known {
+            if config.verbose {
+                println!("[+] Reached posts that were already downloaded, stopping");
+            }
+            break;
+        }
+
+        if page > MAX_PAGE {
+            complete = false;
+            break;
+        }
     }
 
+    report_missing_urls(&queue, config);
+
     unit::Container {
-        tag_name: String::from(tag),
+        tag_name: folder,
         queue,
+        pool: None,
+        synced: newest
+            .filter(|_| complete)
+            .map(|id| (String::from(tag), id))
+            .into_iter()
+            .collect(),
     }
 }
 
-/// Function to build a queue for a pool
-pub async fn build_pool_queue(pool_id: u64, config: &unit::Config) -> unit::Container {
-    println!("[+] Scraping Pool: {}", pool_id);
+/// Function to build a queue for a post set, sets are searched by their short name and downloaded
+/// into a folder named after the set unless another folder is given
+pub async fn build_set_queue(
+    set_id: u64,
+    folder: Option<&str>,
+    config: &unit::Config,
+) -> unit::Container {
+    println!("[+] Scraping Set: {}", set_id);
 
-    let mut queue = VecDeque::new();
+    let app_client = client(config);
 
-    let app_client = reqwest::Client::builder()
-        .user_agent(crate::APP_USER_AGENT)
-        .build()
-        .unwrap();
+    let url = api_url(&format!("post_sets/{}.json", set_id), &[], config);
 
-    let mut url = urlencoding::decode(
-        Url::parse_with_params(
-            "https://e621.net/pools.json",
-            &[("search[id]", pool_id.to_string())],
-        )
+    if config.verbose {
+        println!("{}\n", url);
+    }
+
+    throttle().await;
+    let set = app_client
+        .get(&url)
+        .send()
+        .await
         .unwrap()
-        .as_str(),
-    )
-    .unwrap();
-    if config.sfw {
-        url = urlencoding::decode(
-            Url::parse_with_params(
-                "https://e926.net/pools.json",
-                &[("search[id]", pool_id.to_string())],
-            )
-            .unwrap()
-            .as_str(),
-        )
-        .unwrap();
+        .json::<PostSet>()
+        .await
+        .expect("[-] Failed to get post set");
+
+    // Set folders used to be named after the raw set name
+    let folder = folder.unwrap_or(&set.name);
+    download::move_legacy_folder(&set.name, &download::sanitize(folder), config);
+
+    build_tag_queue_in(&format!("set:{}", set.shortname), folder, config).await
+}
+
+/// Function to build a single queue from every query in a group, skipping posts seen already
+pub async fn build_group_queue(group: &file::Group, config: &unit::Config) -> unit::Container {
+    println!("[+] Scraping Group: {}", group.name);
+
+    let mut queue = VecDeque::new();
+    let mut seen = HashSet::new();
+    let mut synced = Vec::new();
+
+    for entry in &group.queries {
+        let entry_config = config.with_options(&entry.options);
+        let container = build_tag_queue_in(&entry.query, &group.name, &entry_config).await;
+        synced.extend(container.synced);
+
+        for post in container.queue {
+            if seen.insert(post.post_id) {
+                queue.push_back(post);
+            }
+        }
     }
 
+    if config.verbose {
+        println!("Group {} Size: {}\n", group.name, queue.len());
+    }
+
+    unit::Container {
+        tag_name: download::sanitize(&group.name),
+        queue,
+        pool: None,
+        synced,
+    }
+}
+
+/// Function to build a queue for a pool, pools are downloaded into a folder named after the
+/// template in the config unless another folder is given
+pub async fn build_pool_queue(
+    pool_id: u64,
+    folder: Option<&str>,
+    config: &unit::Config,
+) -> unit::Container {
+    println!("[+] Scraping Pool: {}", pool_id);
+
+    let mut queue = VecDeque::new();
+
+    let app_client = client(config);
+
+    let url = api_url(
+        "pools.json",
+        &[("search[id]", &pool_id.to_string())],
+        config,
+    );
+
     if config.verbose {
         println!("{}\n", url);
     }
 
     let batch = get_pool_json(&url, &app_client).await;
-    let post_ids = batch.get(0).unwrap().post_ids.clone();
+    let post_ids = batch.first().unwrap().post_ids.clone();
+    let folder = folder.map_or_else(|| batch.first().unwrap().folder(config), download::sanitize);
 
+    // Pages are named after their position so a missing post leaves a gap in the numbering
+    let mut missing = Vec::new();
     for (counter, id) in post_ids.into_iter().enumerate() {
-        build_pool_post(
-            id,
-            batch.get(0).unwrap().name.clone(),
-            counter,
-            &mut queue,
-            config,
-        )
-        .await;
+        let found = build_pool_post(id, counter, &mut queue, config).await;
+
+        if !found {
+            missing.push(format!("{} (post {})", counter, id));
+        }
     }
 
+    if !missing.is_empty() {
+        println!(
+            "[!] Pool {} is missing {} pages: {}",
+            pool_id,
+            missing.len(),
+            missing.join(", ")
+        );
+    }
+    report_missing_urls(&queue, config);
+
     unit::Container {
-        tag_name: batch.get(0).unwrap().name.clone(),
+        tag_name: folder,
         queue,
+        pool: serde_json::to_value(batch.first().unwrap()).ok(),
+        synced: Vec::new(),
     }
 }
 
-/// Function used to build individual posts from a pool
+/// Function used to build individual posts from a pool, returns false if the post doesn't exist
 pub async fn build_pool_post(
     mut post_id: u64,
-    dir_tag: String,
     post_num: usize,
     queue: &mut VecDeque<unit::Unit>,
     config: &unit::Config,
-) {
-    let app_client = reqwest::Client::builder()
-        .user_agent(crate::APP_USER_AGENT)
-        .build()
-        .unwrap();
+) -> bool {
+    let app_client = client(config);
 
     let old_id = post_id;
     post_id -= 1;
 
-    let mut url = urlencoding::decode(
-        Url::parse_with_params(
-            "https://e621.net/posts.json",
-            &[
-                ("limit", "1"),
-                ("page", &format!("{}{}", "a", post_id.to_string())),
-            ],
-        )
-        .unwrap()
-        .as_str(),
-    )
-    .unwrap();
-    if config.sfw {
-        url = urlencoding::decode(
-            Url::parse_with_params(
-                "https://e926.net/posts.json",
-                &[
-                    ("limit", "1"),
-                    ("page", &format!("{}{}", "a", post_id.to_string())),
-                ],
-            )
-            .unwrap()
-            .as_str(),
-        )
-        .unwrap();
-    }
+    let url = api_url(
+        "posts.json",
+        &[("limit", "1"), ("page", &format!("{}{}", "a", post_id))],
+        config,
+    );
     let batch = get_tag_json(&url, &app_client).await;
 
-    let post = batch.posts.get(0).unwrap();
+    let post = match batch.posts.first() {
+        Some(post) if post.id == old_id => post,
+        _ => return false,
+    };
 
-    if post.id != old_id {
-        return;
-    }
-
-    queue.push_back(unit::Unit {
-        dir_tag,
-        name: post_num.to_string(),
-        ext: post.file.ext.clone(),
-        url: post.file.url.clone(),
-    });
+    queue.push_back(post.to_unit(post_num.to_string(), config));
+    true
 }
 
 /// Function to handle downloading individual posts
-pub async fn build_single_post(mut post_id: u64, config: &unit::Config) -> unit::Container {
+pub async fn build_single_post(post_id: u64, config: &unit::Config) -> unit::Container {
     println!("[+] Scraping Single Post: {}", post_id);
     let mut queue = VecDeque::new();
 
-    post_id -= 1;
+    let app_client = client(config);
 
-    let app_client = reqwest::Client::builder()
-        .user_agent(crate::APP_USER_AGENT)
-        .build()
-        .unwrap();
-
-    let mut url = urlencoding::decode(
-        Url::parse_with_params(
-            "https://e621.net/posts.json",
-            &[
-                ("limit", "1"),
-                ("page", &format!("{}{}", "a", post_id.to_string())),
-            ],
-        )
-        .unwrap()
-        .as_str(),
-    )
-    .unwrap();
-    if config.sfw {
-        url = urlencoding::decode(
-            Url::parse_with_params(
-                "https://e926.net/posts.json",
-                &[
-                    ("limit", "1"),
-                    ("page", &format!("{}{}", "a", post_id.to_string())),
-                ],
-            )
-            .unwrap()
-            .as_str(),
-        )
-        .unwrap();
-    }
+    // The page after the post before it starts with the post itself
+    let url = api_url(
+        "posts.json",
+        &[("limit", "1"), ("page", &format!("{}{}", "a", post_id - 1))],
+        config,
+    );
     let batch = get_tag_json(&url, &app_client).await;
 
-    let post = batch.posts.get(0).unwrap();
+    let post = batch.posts.first().unwrap();
 
-    queue.push_back(unit::Unit {
-        dir_tag: String::from(""),
-        name: post.file.md5.clone(),
-        ext: post.file.ext.clone(),
-        url: post.file.url.clone(),
-    });
+    queue.push_back(post.to_unit(post.file.md5.clone(), config));
+    report_missing_urls(&queue, config);
 
     // TODO: This is dumb, we only need to allocate a single post here so we shouldn't be using a VecDeque fix later
     unit::Container {
         tag_name: post_id.to_string(),
         queue,
+        pool: None,
+        synced: Vec::new(),
     }
 }
 
+/// Function that reports posts the api didn't give a file url for
+fn report_missing_urls(queue: &VecDeque<unit::Unit>, config: &unit::Config) {
+    let missing: Vec<String> = queue
+        .iter()
+        .filter(|u| u.url.is_none())
+        .map(|u| u.post_id.to_string())
+        .collect();
+
+    if missing.is_empty() {
+        return;
+    }
+
+    println!(
+        "[!] {} posts have no file url, they are hidden without logging in or globally blacklisted",
+        missing.len()
+    );
+    println!("[!] Log in or use --reconstruct-urls to download them anyway");
+
+    if config.verbose {
+        println!("[!] Posts without a url: {}", missing.join(" "));
+    }
+}
+
+/// Function that adds the relatives of every post in a Container to it, following the
+/// relationships set in the config up to the depth limit so variant sets end up in one folder
+pub async fn add_relatives(container: &mut unit::Container, config: &unit::Config) {
+    let follow = match config.follow {
+        Some(follow) => follow,
+        None => return,
+    };
+
+    let limit = config.limit.unwrap_or(usize::MAX);
+    let before = container.queue.len();
+
+    let mut seen: HashSet<u64> = container.queue.iter().map(|u| u.post_id).collect();
+    let mut current: Vec<(Option<u64>, Vec<u64>)> = container
+        .queue
+        .iter()
+        .map(|u| (u.parent, u.children.clone()))
+        .collect();
+    let mut added = 0;
+
+    for _ in 0..config.follow_depth {
+        if container.queue.len() >= limit {
+            break;
+        }
+
+        let mut ids = Vec::new();
+        for (parent, children) in &current {
+            if follow != unit::Follow::Children {
+                ids.extend(parent);
+            }
+            if follow != unit::Follow::Parents {
+                ids.extend(children);
+            }
+        }
+        ids.retain(|id| seen.insert(*id));
+
+        if ids.is_empty() {
+            break;
+        }
+
+        let mut found: HashMap<u64, Post> = fetch_posts(&ids, config)
+            .await
+            .into_iter()
+            .map(|p| (p.id, p))
+            .collect();
+        current.clear();
+
+        for id in ids {
+            let post = match found.remove(&id) {
+                Some(post) => post,
+                None => continue,
+            };
+
+            // Relatives are filtered like every other post, but are still walked through so a
+            // hidden parent doesn't cut off the rest of the chain
+            let (parent, children) = post.relationships();
+            current.push((parent, children));
+
+            let tags = post.tags();
+            if post.is_deleted()
+                || query::matches_any(&config.blacklist, &tags)
+                || config.min_score.is_some_and(|min| post.score() < min)
+            {
+                continue;
+            }
+
+            // Relatives count towards the limit of the query
+            if container.queue.len() >= limit {
+                break;
+            }
+
+            container
+                .queue
+                .push_back(post.to_unit(post.file.md5.clone(), config));
+            added += 1;
+        }
+    }
+
+    if added > 0 {
+        println!(
+            "[+] Added {} related posts to {}",
+            added, container.tag_name
+        );
+    }
+
+    // A lone post is downloaded into the base directory, once it has relatives the whole queue goes
+    // into a folder so the file downloaded earlier is moved there instead of downloading it again
+    if before == 1 && container.queue.len() > 1 {
+        let base = download::base_dir(config);
+        let unit = &container.queue[0];
+        let file = unit.file_name();
+
+        if base.join(&file).exists() {
+            let dir = base.join(&container.tag_name);
+            println!(
+                "[+] Moving {} into {} along with its relatives",
+                file,
+                dir.display()
+            );
+
+            if !config.dry_run {
+                mirror::move_post(&base, &file, &dir);
+
+                let mut base_cat = catalog::Catalog::load(&base);
+                if let Some(record) = base_cat.posts.remove(&unit.post_id) {
+                    let mut cat = catalog::Catalog::load(&dir);
+                    cat.posts.insert(unit.post_id, record);
+                    cat.save(&dir);
+                    base_cat.save(&base);
+                }
+            }
+        }
+    }
+}
+
+/// Function that looks up the current state of posts by id, returning a Unit for every post
+/// that still exists along with whether it has been deleted
+pub async fn lookup_posts(ids: &[u64], config: &unit::Config) -> HashMap<u64, (unit::Unit, bool)> {
+    fetch_posts(ids, config)
+        .await
+        .into_iter()
+        .map(|post| {
+            let unit = post.to_unit(post.file.md5.clone(), config);
+            (post.id, (unit, post.is_deleted()))
+        })
+        .collect()
+}
+
+/// Function that fetches posts by id, including deleted ones
+async fn fetch_posts(ids: &[u64], config: &unit::Config) -> Vec<Post> {
+    let app_client = client(config);
+
+    let mut posts = Vec::new();
+
+    // e621 allows a list of ids in a single search, deleted posts only show up with status:any
+    for chunk in ids.chunks(100) {
+        let id_list: Vec<String> = chunk.iter().map(|id| id.to_string()).collect();
+        let tags = format!("id:{} status:any", id_list.join(","));
+
+        let url = api_url("posts.json", &[("limit", "100"), ("tags", &tags)], config);
+
+        if config.verbose {
+            println!("{}", url);
+        }
+
+        posts.extend(get_tag_json(&url, &app_client).await.posts);
+    }
+
+    posts
+}
+
+/// Function to fetch the wiki page of a tag
+pub async fn get_wiki_page(title: &str, config: &unit::Config) -> Option<Value> {
+    let url = api_url("wiki_pages.json", &[("search[title]", title)], config);
+    let mut value = get_info_json(&url, config).await?;
+    Some(value.get_mut(0)?.take())
+}
+
+/// Function to fetch the raw metadata of a single post
+pub async fn get_post_info(post_id: u64, config: &unit::Config) -> Option<Value> {
+    let url = api_url(&format!("posts/{}.json", post_id), &[], config);
+    let mut value = get_info_json(&url, config).await?;
+    Some(value["post"].take())
+}
+
+/// Function to fetch the raw metadata of a pool
+pub async fn get_pool_info(pool_id: u64, config: &unit::Config) -> Option<Value> {
+    let url = api_url(&format!("pools/{}.json", pool_id), &[], config);
+    get_info_json(&url, config).await
+}
+
+/// Function to build an api url pointing at e621 or e926 depending on the config
+pub fn api_url(endpoint: &str, params: &[(&str, &str)], config: &unit::Config) -> String {
+    let host = if config.sfw {
+        "https://e926.net"
+    } else {
+        "https://e621.net"
+    };
+
+    let url = Url::parse_with_params(&format!("{}/{}", host, endpoint), params).unwrap();
+    urlencoding::decode(url.as_str()).unwrap()
+}
+
+/// Function that builds the headers every api request is sent with, the login details are sent
+/// as basic auth so they never end up in urls that get printed
+pub fn auth_headers(config: &unit::Config) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    if let (Some(username), Some(api_key)) = (&config.username, &config.api_key) {
+        let token = STANDARD.encode(format!("{}:{}", username, api_key));
+        let mut value = HeaderValue::from_str(&format!("Basic {}", token))
+            .expect("[-] Invalid characters in username or api key");
+        value.set_sensitive(true);
+        headers.insert(AUTHORIZATION, value);
+    }
+
+    headers
+}
+
+/// Function to build the client used for api requests
+pub fn client(config: &unit::Config) -> reqwest::Client {
+    reqwest::Client::builder()
+        .user_agent(crate::APP_USER_AGENT)
+        .default_headers(auth_headers(config))
+        .build()
+        .unwrap()
+}
+
+/// Function that reserves a slot for an api request, returning how long to wait for it
+fn reserve_request() -> Duration {
+    let mut next = NEXT_REQUEST.lock().unwrap();
+    let now = Instant::now();
+    let slot = (*next).max(now);
+    *next = slot + REQUEST_GAP;
+    slot - now
+}
+
+/// Function that waits until an api request is allowed to go out
+pub async fn throttle() {
+    tokio::time::sleep(reserve_request()).await;
+}
+
+/// Function that waits until an api request is allowed to go out, for the download workers
+pub fn throttle_blocking() {
+    std::thread::sleep(reserve_request());
+}
+
 async fn get_tag_json(url: &str, client: &reqwest::Client) -> TagPayload {
+    throttle().await;
     client
         .get(url)
         .send()
@@ -305,6 +926,7 @@ async fn get_tag_json(url: &str, client: &reqwest::Client) -> TagPayload {
 }
 
 async fn get_pool_json(url: &str, client: &reqwest::Client) -> Vec<Pool> {
+    throttle().await;
     client
         .get(url)
         .send()
@@ -314,3 +936,20 @@ async fn get_pool_json(url: &str, client: &reqwest::Client) -> Vec<Pool> {
         .await
         .unwrap()
 }
+
+async fn get_info_json(url: &str, config: &unit::Config) -> Option<Value> {
+    let app_client = client(config);
+
+    throttle().await;
+    let response = app_client.get(url).send().await.unwrap();
+
+    if config.verbose {
+        println!("{}", url);
+    }
+
+    if !response.status().is_success() {
+        return None;
+    }
+
+    response.json::<Value>().await.ok()
+}
@@ -0,0 +1,386 @@
+use crate::file;
+use crate::unit;
+
+use serde::Deserialize;
+use std::fs;
+use std::path;
+
+/// Template written by `se621 config init`
+const TEMPLATE: &str = r#"# Se621 configuration file
+# Every setting is optional, command line flags override the values set here
+
+# Only download safe for work images from e926
+# sfw = false
+
+# Output more detail during the scraping and downloading process
+# verbose = false
+
+# The directory to download the files to, defaults to the current directory
+# directory = "/path/to/archive"
+
+# The amount of times to try downloading a file
+# tries = 10
+
+# The amount of threads used to download files
+# workers = 8
+
+# The maximum amount of posts to download per query
+# limit = 500
+
+# The order posts are walked in when a limit is set, one of oldest, newest or score
+# order = "oldest"
+
+# The rendition to download, one of original, sample or preview
+# posts without a sample or preview fall back to the original
+# variant = "original"
+
+# Save the metadata of every post as a json file next to it
+# sidecar = false
+
+# Walk tags newest first and stop at the first post that was already downloaded,
+# sync always does this
+# incremental = false
+
+# Move files of posts that no longer match their query into the trash directory,
+# this always walks the whole query
+# prune = false
+
+# Package every downloaded pool into the exports directory, one of cbz, epub or pdf
+# export = "cbz"
+
+# How pool folders are named, {id}, {name} and {category} are replaced with the details of the pool
+# pool_folder = "{id} - {name}"
+
+# Also download the children, parents or both of every post that was found, posts are added to the
+# same folder so variant sets stay together
+# follow = "children"
+
+# How many relationship steps are followed from the posts that were found
+# follow_depth = 1
+
+# Save the description the artist wrote for a post as a markdown file next to it
+# commentary = false
+
+# Save the wiki page of every tag in a query into a wiki folder inside the folder of the query
+# wiki = false
+
+# Save the notes of a post, like translations, as a json file next to it
+# notes = false
+
+# Also write a html page showing the notes on top of the image, needs notes
+# note_overlay = false
+
+# Save the comments of a post as a json file next to it
+# comments = false
+
+# Save the source urls of a post, one per line, as a text file next to it
+# sources = false
+
+# Command that is run for every downloaded post with sources, it gets the source urls as arguments
+# and the post metadata as json on stdin, and runs inside the folder of the post so it can save
+# originals next to the e621 file
+# source_command = "fetch-originals --best"
+
+# Rebuild the file url from the md5 for posts the api hides the url of
+# reconstruct_urls = false
+
+# Make sync check every downloaded post for deletions and replacements
+# mirror = false
+
+# Keep the old file of replaced posts in the replaced directory instead of removing it
+# keep_replaced = true
+
+# Move deleted posts into the deleted directory instead of only flagging them
+# move_deleted = false
+
+# Check tags for aliases and empty results before scraping
+# preflight = true
+
+# Replace aliased tags with the tag they are aliased to
+# rewrite_aliases = false
+
+[credentials]
+# Your e621 username and api key, found under Account > Manage API Access
+# username = ""
+# api_key = ""
+
+[filters]
+# Posts with any of these tags are never downloaded, wildcards are supported
+# blacklist = ["tag_one", "tag_two*"]
+
+# Posts with a score lower than this are skipped
+# min_score = 0
+
+[queries]
+# Queries use the same syntax as the tags file, options can be added after a |
+# e621 urls for posts, pools, searches and sets can be used in any list
+# general = ["wolf solo | limit=100", "https://e621.net/posts?tags=fox+solo"]
+# pools = ["1234"]
+# single_posts = ["5678"]
+# sets = ["910"]
+
+# Every query in a group is downloaded into the same folder
+# [[queries.groups]]
+# name = "canines | min_score=20"
+# queries = ["~wolf ~fox ~dog"]
+"#;
+
+/// Struct that represents the config file
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    sfw: Option<bool>,
+    verbose: Option<bool>,
+    directory: Option<String>,
+    tries: Option<usize>,
+    workers: Option<usize>,
+    limit: Option<usize>,
+    order: Option<unit::Order>,
+    variant: Option<unit::Variant>,
+    sidecar: Option<bool>,
+    incremental: Option<bool>,
+    prune: Option<bool>,
+    export: Option<unit::Export>,
+    pool_folder: Option<String>,
+    follow: Option<unit::Follow>,
+    follow_depth: Option<usize>,
+    commentary: Option<bool>,
+    wiki: Option<bool>,
+    notes: Option<bool>,
+    note_overlay: Option<bool>,
+    comments: Option<bool>,
+    sources: Option<bool>,
+    source_command: Option<String>,
+    reconstruct_urls: Option<bool>,
+    mirror: Option<bool>,
+    keep_replaced: Option<bool>,
+    move_deleted: Option<bool>,
+    preflight: Option<bool>,
+    rewrite_aliases: Option<bool>,
+    credentials: Credentials,
+    filters: Filters,
+    queries: Queries,
+}
+
+/// Struct that represents the credentials section of the config file
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Credentials {
+    username: Option<String>,
+    api_key: Option<String>,
+}
+
+/// Struct that represents the filters section of the config file
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Filters {
+    blacklist: Vec<String>,
+    min_score: Option<i64>,
+}
+
+/// Struct that represents the queries section of the config file
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Queries {
+    general: Vec<String>,
+    pools: Vec<String>,
+    single_posts: Vec<String>,
+    sets: Vec<String>,
+    groups: Vec<GroupQueries>,
+}
+
+/// Struct that represents a group in the queries section of the config file
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct GroupQueries {
+    name: String,
+    queries: Vec<String>,
+}
+
+impl Settings {
+    /// Function that copies every value set in the config file into the config
+    pub fn apply(&self, config: &mut unit::Config) {
+        if let Some(sfw) = self.sfw {
+            config.sfw = sfw;
+        }
+        if let Some(verbose) = self.verbose {
+            config.verbose = verbose;
+        }
+        if self.directory.is_some() {
+            config.directory = self.directory.clone();
+        }
+        if let Some(tries) = self.tries {
+            config.tries = tries;
+        }
+        if let Some(workers) = self.workers {
+            config.workers = workers;
+        }
+        if self.limit.is_some() {
+            config.limit = self.limit;
+        }
+        if let Some(order) = self.order {
+            config.order = order;
+        }
+        if let Some(variant) = self.variant {
+            config.variant = variant;
+        }
+        if let Some(sidecar) = self.sidecar {
+            config.sidecar = sidecar;
+        }
+        if let Some(incremental) = self.incremental {
+            config.incremental = incremental;
+        }
+        if let Some(prune) = self.prune {
+            config.prune = prune;
+        }
+        if self.export.is_some() {
+            config.export = self.export;
+        }
+        if let Some(pool_folder) = &self.pool_folder {
+            config.pool_folder = pool_folder.clone();
+        }
+        if self.follow.is_some() {
+            config.follow = self.follow;
+        }
+        if let Some(follow_depth) = self.follow_depth {
+            config.follow_depth = follow_depth;
+        }
+        if let Some(commentary) = self.commentary {
+            config.commentary = commentary;
+        }
+        if let Some(wiki) = self.wiki {
+            config.wiki = wiki;
+        }
+        if let Some(notes) = self.notes {
+            config.notes = notes;
+        }
+        if let Some(note_overlay) = self.note_overlay {
+            config.note_overlay = note_overlay;
+        }
+        if let Some(comments) = self.comments {
+            config.comments = comments;
+        }
+        if let Some(sources) = self.sources {
+            config.sources = sources;
+        }
+        if self.source_command.is_some() {
+            config.source_command = self.source_command.clone();
+        }
+        if let Some(reconstruct_urls) = self.reconstruct_urls {
+            config.reconstruct_urls = reconstruct_urls;
+        }
+        if let Some(mirror) = self.mirror {
+            config.mirror = mirror;
+        }
+        if let Some(keep_replaced) = self.keep_replaced {
+            config.keep_replaced = keep_replaced;
+        }
+        if let Some(move_deleted) = self.move_deleted {
+            config.move_deleted = move_deleted;
+        }
+        if let Some(preflight) = self.preflight {
+            config.preflight = preflight;
+        }
+        if let Some(rewrite_aliases) = self.rewrite_aliases {
+            config.rewrite_aliases = rewrite_aliases;
+        }
+        if self.credentials.username.is_some() {
+            config.username = self.credentials.username.clone();
+        }
+        if self.credentials.api_key.is_some() {
+            config.api_key = self.credentials.api_key.clone();
+        }
+        if self.filters.min_score.is_some() {
+            config.min_score = self.filters.min_score;
+        }
+        config.blacklist = self.filters.blacklist.clone();
+    }
+
+    /// Function to check whether the config file contains any queries
+    pub fn has_queries(&self) -> bool {
+        !self.queries.general.is_empty()
+            || !self.queries.pools.is_empty()
+            || !self.queries.single_posts.is_empty()
+            || !self.queries.sets.is_empty()
+            || !self.queries.groups.is_empty()
+    }
+
+    /// Function that converts the queries in the config file into the same form as the tags file
+    pub fn tags(&self) -> file::TagStore {
+        let parse = |list: &[String]| list.iter().map(|q| file::Entry::parse(q)).collect();
+
+        file::TagStore {
+            general: parse(&self.queries.general),
+            pools: parse(&self.queries.pools),
+            single_posts: parse(&self.queries.single_posts),
+            sets: parse(&self.queries.sets),
+            groups: self
+                .queries
+                .groups
+                .iter()
+                .map(|g| {
+                    let header = file::Entry::parse(&g.name);
+                    file::Group {
+                        name: header.query,
+                        options: header.options,
+                        queries: parse(&g.queries),
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Function that finds the config file, either the one specified or the one in the config directory
+pub fn config_path(config_file: Option<&str>) -> Option<path::PathBuf> {
+    match config_file {
+        Some(config_file) => Some(path::PathBuf::from(config_file)),
+        None => dirs::config_dir().map(|mut dir| {
+            dir.push("se621");
+            dir.push("config.toml");
+            dir
+        }),
+    }
+}
+
+/// Function that handles loading and parsing the config file if there is one
+pub fn load(config_file: Option<&str>) -> Option<Settings> {
+    let config_filepath = config_path(config_file)?;
+
+    if !config_filepath.exists() {
+        if config_file.is_some() {
+            println!("[-] Specified config file doesn't exist");
+            std::process::exit(1);
+        }
+        return None;
+    }
+
+    let data = fs::read_to_string(&config_filepath).expect("[-] Failed to read config file");
+    match toml::from_str::<Settings>(&data) {
+        Ok(settings) => Some(settings),
+        Err(e) => {
+            println!("[-] Problem with config file: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Function that writes a commented config file template
+pub fn init(config_file: Option<&str>) {
+    let config_filepath = config_path(config_file).expect("[-] Couldn't find the config directory");
+
+    if config_filepath.exists() {
+        println!(
+            "[-] Config file already exists: {}",
+            config_filepath.display()
+        );
+        std::process::exit(1);
+    }
+
+    if let Some(parent) = config_filepath.parent() {
+        fs::create_dir_all(parent).expect("[-] Failed to create config directory");
+    }
+
+    fs::write(&config_filepath, TEMPLATE).expect("[-] Failed to write config file");
+    println!("[+] Created config file: {}", config_filepath.display());
+}
@@ -0,0 +1,149 @@
+use crate::unit;
+
+use serde_json::{json, Value};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Function that writes the source urls of a post next to its file when they are wanted, the list
+/// is rewritten every time since sources are often added after a post is uploaded
+pub fn save(post: &unit::Unit, dir: &Path, config: &unit::Config) {
+    if !config.sources || post.sources.is_empty() {
+        return;
+    }
+
+    fs::write(
+        dir.join(format!("{}.sources.txt", post.name)),
+        post.sources.join("\n") + "\n",
+    )
+    .expect("[-] Failed to write sources file");
+}
+
+/// Function that runs the source command of the config for a downloaded post, the command gets the
+/// source urls as arguments and the post metadata as json on stdin, and runs inside the folder of
+/// the post. Its result is saved next to the file so it is only run again when it failed
+pub fn run_command(post: &unit::Unit, file: &Path, config: &unit::Config) {
+    let command = match &config.source_command {
+        Some(command) => command,
+        None => return,
+    };
+
+    // Only web urls are passed on so a source can't be mistaken for an option of the command
+    let urls: Vec<&String> = post
+        .sources
+        .iter()
+        .filter(|s| s.starts_with("http://") || s.starts_with("https://"))
+        .collect();
+    if urls.is_empty() {
+        return;
+    }
+
+    let dir = file.parent().unwrap();
+    let result_path = dir.join(format!("{}.source.json", post.name));
+    if let Ok(data) = fs::read_to_string(&result_path) {
+        if serde_json::from_str::<Value>(&data).is_ok_and(|r| r["success"] == true) {
+            return;
+        }
+    }
+
+    // The command is split on whitespace, the source urls are added after its own arguments and a
+    // `--` so the command stops parsing options before them
+    let mut parts = command.split_whitespace();
+    let program = match parts.next() {
+        Some(program) => program,
+        None => return,
+    };
+
+    let child = Command::new(program)
+        .args(parts)
+        .arg("--")
+        .args(&urls)
+        .current_dir(dir)
+        .env("SE621_POST_ID", post.post_id.to_string())
+        .env("SE621_NAME", &post.name)
+        .env("SE621_FILE", file)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+
+    let (status, files, stderr) = match child {
+        Ok(mut child) => {
+            // The metadata is written from another thread so a command that prints a lot before
+            // reading stdin can't block on a full pipe, commands that don't read it close stdin
+            // early which isn't an error
+            let writer = child.stdin.take().map(|mut stdin| {
+                let data = metadata(post, file).to_string();
+                thread::spawn(move || {
+                    let _ = stdin.write_all(data.as_bytes());
+                })
+            });
+
+            let output = child
+                .wait_with_output()
+                .expect("[-] Failed to wait for the source command");
+            if let Some(writer) = writer {
+                let _ = writer.join();
+            }
+
+            // Every line the command prints is taken as a file it saved
+            let files: Vec<String> = String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(String::from)
+                .collect();
+
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            (Some(output.status), files, stderr)
+        }
+        Err(e) => (None, Vec::new(), e.to_string()),
+    };
+
+    let success = status.is_some_and(|s| s.success());
+    if !success {
+        println!(
+            "[-] Source command failed for post {}: {}",
+            post.post_id, stderr
+        );
+    } else if config.verbose {
+        println!(
+            "[+] Source command saved {} files for post {}",
+            files.len(),
+            post.post_id
+        );
+    }
+
+    let result = json!({
+        "command": command,
+        "sources": urls,
+        "success": success,
+        "status": status.and_then(|s| s.code()),
+        "files": files,
+        "stderr": stderr,
+    });
+
+    fs::write(result_path, serde_json::to_string_pretty(&result).unwrap())
+        .expect("[-] Failed to write source command result");
+}
+
+/// Function that builds the metadata the source command gets, the full post is included when
+/// sidecar files are wanted
+fn metadata(post: &unit::Unit, file: &Path) -> Value {
+    json!({
+        "id": post.post_id,
+        "md5": post.md5,
+        "name": post.name,
+        "file": file,
+        "url": post.url,
+        "width": post.width,
+        "height": post.height,
+        "artists": post.artists,
+        "parent": post.parent,
+        "children": post.children,
+        "sources": post.sources,
+        "post": post.meta,
+    })
+}
@@ -1,27 +1,196 @@
+use crate::file;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::VecDeque;
+use std::str::FromStr;
 
 /// Struct for holding information about a sinble post that needs downloading
 #[derive(Debug)]
 pub struct Unit {
-    pub dir_tag: String,
+    pub post_id: u64,
     pub name: String,
+    pub md5: String,
     pub ext: String,
     pub url: Option<String>,
+    pub size: u64,
+    pub width: u64,
+    pub height: u64,
+    pub variant: Variant,
+    pub artists: Vec<String>,
+    pub commentary: Option<String>,
+    pub parent: Option<u64>,
+    pub children: Vec<u64>,
+    pub sources: Vec<String>,
+    pub meta: Option<Value>,
+}
+
+impl Unit {
+    /// Function that gets the name of the downloaded file, samples and previews are marked in the
+    /// name so they never take the place of the original
+    pub fn file_name(&self) -> String {
+        format!("{}{}.{}", self.name, self.variant.suffix(), self.ext)
+    }
 }
 
-/// Struct for holding the tag name and the queue holding all the posts for that tag
+/// Struct for holding the tag name and the queue holding all the posts for that tag, pools
+/// also keep the metadata of the pool and every query walked in full the newest post it saw
 #[derive(Debug)]
 pub struct Container {
     pub tag_name: String,
     pub queue: VecDeque<Unit>,
+    pub pool: Option<Value>,
+    pub synced: Vec<(String, u64)>,
+}
+
+/// Enum for the order posts of a tag are walked in, which matters when a limit is set
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    Oldest,
+    Newest,
+    Score,
+}
+
+impl FromStr for Order {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "oldest" => Ok(Order::Oldest),
+            "newest" => Ok(Order::Newest),
+            "score" => Ok(Order::Score),
+            e => Err(format!(
+                "unknown order {}, expected oldest, newest or score",
+                e
+            )),
+        }
+    }
+}
+
+/// Enum for the rendition of a post that gets downloaded
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Variant {
+    #[default]
+    Original,
+    Sample,
+    Preview,
+}
+
+impl Variant {
+    /// Function that gets the part added to the name of files downloaded as this rendition
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Variant::Original => "",
+            Variant::Sample => ".sample",
+            Variant::Preview => ".preview",
+        }
+    }
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "original" => Ok(Variant::Original),
+            "sample" => Ok(Variant::Sample),
+            "preview" => Ok(Variant::Preview),
+            e => Err(format!(
+                "unknown variant {}, expected original, sample or preview",
+                e
+            )),
+        }
+    }
+}
+
+/// Enum for the formats pools can be exported to once they are downloaded
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Export {
+    Cbz,
+    Epub,
+    Pdf,
+}
+
+impl FromStr for Export {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cbz" => Ok(Export::Cbz),
+            "epub" => Ok(Export::Epub),
+            "pdf" => Ok(Export::Pdf),
+            e => Err(format!(
+                "unknown export format {}, expected cbz, epub or pdf",
+                e
+            )),
+        }
+    }
+}
+
+/// Enum for the relationships that are followed from the posts that were found
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Follow {
+    Children,
+    Parents,
+    Both,
+}
+
+impl FromStr for Follow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "children" => Ok(Follow::Children),
+            "parents" => Ok(Follow::Parents),
+            "both" => Ok(Follow::Both),
+            e => Err(format!(
+                "unknown relationship {}, expected children, parents or both",
+                e
+            )),
+        }
+    }
 }
 
 /// Struct for holding config information so it can be easly passed around
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
     pub sfw: bool,
     pub verbose: bool,
     pub directory: Option<String>,
+    pub preflight: bool,
+    pub rewrite_aliases: bool,
+    pub tries: usize,
+    pub workers: usize,
+    pub limit: Option<usize>,
+    pub order: Order,
+    pub variant: Variant,
+    pub min_score: Option<i64>,
+    pub sidecar: bool,
+    pub incremental: bool,
+    pub prune: bool,
+    pub export: Option<Export>,
+    pub pool_folder: String,
+    pub follow: Option<Follow>,
+    pub follow_depth: usize,
+    pub commentary: bool,
+    pub wiki: bool,
+    pub notes: bool,
+    pub note_overlay: bool,
+    pub comments: bool,
+    pub sources: bool,
+    pub source_command: Option<String>,
+    pub reconstruct_urls: bool,
+    pub mirror: bool,
+    pub keep_replaced: bool,
+    pub move_deleted: bool,
+    pub dry_run: bool,
+    pub blacklist: Vec<String>,
+    pub username: Option<String>,
+    pub api_key: Option<String>,
 }
 
 impl Config {
@@ -30,6 +199,101 @@ impl Config {
             sfw,
             verbose,
             directory,
+            preflight: true,
+            rewrite_aliases: false,
+            tries: 10,
+            workers: 8,
+            limit: None,
+            order: Order::Oldest,
+            variant: Variant::Original,
+            min_score: None,
+            sidecar: false,
+            incremental: false,
+            prune: false,
+            export: None,
+            pool_folder: String::from("{id} - {name}"),
+            follow: None,
+            follow_depth: 1,
+            commentary: false,
+            wiki: false,
+            notes: false,
+            note_overlay: false,
+            comments: false,
+            sources: false,
+            source_command: None,
+            reconstruct_urls: false,
+            mirror: false,
+            keep_replaced: true,
+            move_deleted: false,
+            dry_run: false,
+            blacklist: Vec::new(),
+            username: None,
+            api_key: None,
+        }
+    }
+
+    /// Function that creates a copy of the config with the options of a tag file entry applied
+    pub fn with_options(&self, options: &file::EntryOptions) -> Self {
+        let mut config = self.clone();
+
+        if options.dir.is_some() {
+            config.directory = options.dir.clone();
+        }
+        if let Some(sfw) = options.sfw {
+            config.sfw = sfw;
+        }
+        if let Some(workers) = options.workers {
+            config.workers = workers;
+        }
+        if let Some(tries) = options.tries {
+            config.tries = tries;
+        }
+        if options.limit.is_some() {
+            config.limit = options.limit;
         }
+        if let Some(order) = options.order {
+            config.order = order;
+        }
+        if let Some(variant) = options.variant {
+            config.variant = variant;
+        }
+        if options.min_score.is_some() {
+            config.min_score = options.min_score;
+        }
+        if let Some(sidecar) = options.sidecar {
+            config.sidecar = sidecar;
+        }
+        if let Some(incremental) = options.incremental {
+            config.incremental = incremental;
+        }
+        if let Some(prune) = options.prune {
+            config.prune = prune;
+        }
+        if options.export.is_some() {
+            config.export = options.export;
+        }
+        if options.follow.is_some() {
+            config.follow = options.follow;
+        }
+        if let Some(follow_depth) = options.follow_depth {
+            config.follow_depth = follow_depth;
+        }
+        if let Some(commentary) = options.commentary {
+            config.commentary = commentary;
+        }
+        if let Some(wiki) = options.wiki {
+            config.wiki = wiki;
+        }
+        if let Some(notes) = options.notes {
+            config.notes = notes;
+        }
+        if let Some(comments) = options.comments {
+            config.comments = comments;
+        }
+        if let Some(sources) = options.sources {
+            config.sources = sources;
+        }
+
+        config
     }
 }
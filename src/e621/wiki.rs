@@ -0,0 +1,76 @@
+use crate::download;
+use crate::dtext;
+use crate::query;
+use crate::scraper;
+use crate::unit;
+
+use serde_json::Value;
+use std::fs;
+
+/// The folder inside the folder of a query wiki pages are saved in
+pub const WIKI_DIR: &str = "wiki";
+
+/// Function that saves the wiki page of every tag in a query into the folder of the query, both
+/// as the json the api returns and as markdown
+pub async fn save(query: &str, tag_dir: &str, config: &unit::Config) {
+    // Wiki pages always go into the folder of the query, even when its posts end up in the base
+    // directory, so they never mix with the wiki pages of other queries
+    let dir = download::base_dir(config).join(tag_dir).join(WIKI_DIR);
+
+    for tag in query::plain_tags(query) {
+        let page = match scraper::get_wiki_page(tag, config).await {
+            Some(page) => page,
+            None => {
+                if config.verbose {
+                    println!("[-] No wiki page for {}", tag);
+                }
+                continue;
+            }
+        };
+
+        if config.dry_run {
+            println!("[+] Would save the wiki page of {}", tag);
+            continue;
+        }
+
+        fs::create_dir_all(&dir).expect("[-] Failed to create wiki directory");
+
+        let name = download::sanitize(tag);
+        fs::write(
+            dir.join(format!("{}.json", name)),
+            serde_json::to_string_pretty(&page).unwrap(),
+        )
+        .expect("[-] Failed to write wiki page");
+        fs::write(dir.join(format!("{}.md", name)), markdown(tag, &page))
+            .expect("[-] Failed to write wiki page");
+
+        if config.verbose {
+            println!("[+] Saved the wiki page of {}", tag);
+        }
+    }
+}
+
+/// Function that turns a wiki page into a markdown document
+fn markdown(tag: &str, page: &Value) -> String {
+    let title = page["title"].as_str().unwrap_or(tag).replace('_', " ");
+    let mut text = format!("# {}\n\n", title);
+
+    text.push_str(&dtext::to_markdown(page["body"].as_str().unwrap_or("")));
+    text.push('\n');
+
+    let other_names: Vec<&str> = page["other_names"]
+        .as_array()
+        .map(|names| names.iter().filter_map(|n| n.as_str()).collect())
+        .unwrap_or_default();
+    if !other_names.is_empty() {
+        text.push_str(&format!("\nOther names: {}\n", other_names.join(", ")));
+    }
+
+    text.push_str(&format!(
+        "\nSource: https://e621.net/wiki_pages/show_or_new?title={}\nUpdated: {}\n",
+        urlencoding::encode(tag),
+        page["updated_at"].as_str().unwrap_or("unknown")
+    ));
+
+    text
+}
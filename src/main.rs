@@ -7,17 +7,31 @@
 //! 3. Run the program.
 mod e621;
 
+use e621::catalog;
+use e621::commands;
 use e621::download;
+use e621::dtext;
+use e621::export;
 use e621::file;
+use e621::link;
+use e621::mirror;
+use e621::notes;
+use e621::pool;
+use e621::preflight;
+use e621::query;
 use e621::scraper;
+use e621::settings;
+use e621::sources;
 use e621::unit;
+use e621::wiki;
 
-use std::collections::VecDeque;
 extern crate clap;
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
 
 pub const APP_USER_AGENT: &str = "Se621/0.5.1";
 pub const MAX_CHAN_COUNT_TRY: usize = 20;
+pub const MAX_SERVER_TAGS: usize = 40;
+pub const MAX_FOLDER_LEN: usize = 150;
 pub const BANNER: &str = "   _____ ______   ________  ___\n  / ___// ____/  / ___/__ \\<  /\n  \\__ \\/ __/    / __ \\__/ // / \n ___/ / /___   / /_/ / __// /  \n/____/_____/   \\____/____/_/   \n";
 
 #[tokio::main]
@@ -27,13 +41,23 @@ async fn main() {
         .version("0.5")
         .author("Asriel <Asriel@dismail.de>")
         .about("Downloads images from e621/e926 concurrently")
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .help("The config file to use instead of the one in the config directory")
+                .takes_value(true)
+                .global(true),
+        )
         .arg(
             Arg::with_name("tag-file")
                 .short("f")
                 .long("tag-file")
                 .value_name("FILE")
                 .help("The file containing the tags you want to download")
-                .takes_value(true),
+                .takes_value(true)
+                .global(true),
         )
         .arg(
             Arg::with_name("sfw")
@@ -41,7 +65,8 @@ async fn main() {
                 .long("sfw")
                 .value_name("SFW")
                 .help("Set's the scraper to only download safe for work images")
-                .takes_value(false),
+                .takes_value(false)
+                .global(true),
         )
         .arg(
             Arg::with_name("verbose")
@@ -49,7 +74,8 @@ async fn main() {
                 .long("verbose")
                 .value_name("VERBOSE")
                 .help("Output more detail during the scraping and downloading process")
-                .takes_value(false),
+                .takes_value(false)
+                .global(true),
         )
         .arg(
             Arg::with_name("directory")
@@ -57,94 +83,499 @@ async fn main() {
                 .long("directory")
                 .value_name("DIRECTORY")
                 .help("The directory to download the files to")
-                .takes_value(true),
+                .takes_value(true)
+                .global(true),
         )
         .arg(
             Arg::with_name("tries")
                 .short("t")
                 .long("tries")
                 .value_name("TRIES")
-                .help("The amount of times to try downloading a file")
-                .default_value("10")
-                .takes_value(true),
+                .help("The amount of times to try downloading a file [default: 10]")
+                .takes_value(true)
+                .global(true),
         )
         .arg(
             Arg::with_name("workers")
                 .short("w")
                 .long("workers")
                 .value_name("WORKERS")
-                .help("The amount of threads you want to use to download filse")
-                .default_value("8")
-                .takes_value(true),
+                .help("The amount of threads you want to use to download filse [default: 8]")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("limit")
+                .short("l")
+                .long("limit")
+                .value_name("LIMIT")
+                .help("The maximum amount of posts to download per query")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("order")
+                .short("o")
+                .long("order")
+                .value_name("ORDER")
+                .help("The order posts are walked in when a limit is set")
+                .possible_values(&["oldest", "newest", "score"])
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("variant")
+                .long("variant")
+                .value_name("VARIANT")
+                .help("The rendition of every post to download")
+                .possible_values(&["original", "sample", "preview"])
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("export")
+                .long("export")
+                .value_name("FORMAT")
+                .help("Package every downloaded pool into the exports directory")
+                .possible_values(&["cbz", "epub", "pdf"])
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("follow")
+                .long("follow")
+                .value_name("RELATIONSHIP")
+                .help("Also download the children, parents or both of every post")
+                .possible_values(&["children", "parents", "both"])
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("follow-depth")
+                .long("follow-depth")
+                .value_name("DEPTH")
+                .help("How many relationship steps are followed, defaults to 1")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("pool-folder")
+                .long("pool-folder")
+                .value_name("TEMPLATE")
+                .help("How pool folders are named, e.g. \"{id} - {name}\"")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("reconstruct-urls")
+                .long("reconstruct-urls")
+                .help("Rebuild the file url from the md5 for posts the api hides the url of")
+                .takes_value(false)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("incremental")
+                .short("i")
+                .long("incremental")
+                .help("Walk tags newest first and stop at the first post already downloaded")
+                .takes_value(false)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("prune")
+                .long("prune")
+                .help(
+                    "Move files of posts that no longer match their query into the trash directory",
+                )
+                .takes_value(false)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("commentary")
+                .long("commentary")
+                .help("Save the description the artist wrote for a post as a markdown file next to it")
+                .takes_value(false)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("wiki")
+                .long("wiki")
+                .help("Save the wiki page of every tag in a query next to its files")
+                .takes_value(false)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("notes")
+                .long("notes")
+                .help("Save the notes of every post as a json file next to it")
+                .takes_value(false)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("note-overlay")
+                .long("note-overlay")
+                .help("Also write a html page showing the notes on top of the image")
+                .takes_value(false)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("comments")
+                .long("comments")
+                .help("Save the comments of every post as a json file next to it")
+                .takes_value(false)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("sources")
+                .long("sources")
+                .help("Save the source urls of every post as a text file next to it")
+                .takes_value(false)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("source-command")
+                .long("source-command")
+                .value_name("COMMAND")
+                .help("Run a command with the source urls of every downloaded post")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("sidecar")
+                .long("sidecar")
+                .help("Save the metadata of every post as a json file next to it")
+                .takes_value(false)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .short("n")
+                .long("dry-run")
+                .help("Scrape and report what would be downloaded without writing anything")
+                .takes_value(false)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("rewrite-aliases")
+                .short("r")
+                .long("rewrite-aliases")
+                .help("Replace aliased tags with the tag they are aliased to before scraping")
+                .takes_value(false)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("skip-preflight")
+                .long("skip-preflight")
+                .help("Don't check tags for aliases and empty results before scraping")
+                .takes_value(false)
+                .global(true),
+        )
+        .subcommand(
+            SubCommand::with_name("download")
+                .about("Download everything in the tags file, this is the default"),
+        )
+        .subcommand(
+            SubCommand::with_name("get")
+                .about("Download the given queries, pools or posts without using the tags file")
+                .arg(
+                    Arg::with_name("query")
+                        .value_name("QUERY")
+                        .help("The tags or e621 url to download, use - to read queries from stdin")
+                        .multiple(true),
+                )
+                .arg(
+                    Arg::with_name("pool")
+                        .long("pool")
+                        .value_name("POOL_ID")
+                        .help("A pool to download")
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("post")
+                        .long("post")
+                        .value_name("POST_ID")
+                        .help("A single post to download")
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("set")
+                        .long("set")
+                        .value_name("SET_ID")
+                        .help("A post set to download")
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("stdin")
+                        .long("stdin")
+                        .help("Read queries from stdin, one per line"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sync")
+                .about("Update the archive with new posts and report what was added")
+                .arg(
+                    Arg::with_name("mirror")
+                        .short("m")
+                        .long("mirror")
+                        .help("Check every downloaded post for deletions and replacements"),
+                )
+                .arg(
+                    Arg::with_name("move-deleted").long("move-deleted").help(
+                        "Move deleted posts into the deleted directory instead of flagging them",
+                    ),
+                )
+                .arg(
+                    Arg::with_name("discard-replaced")
+                        .long("discard-replaced")
+                        .help("Remove the old file of replaced posts instead of keeping it"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Print the posts matching a query without downloading them")
+                .arg(
+                    Arg::with_name("query")
+                        .value_name("QUERY")
+                        .help("The tags to search for")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Show the metadata of a post or pool")
+                .arg(
+                    Arg::with_name("id")
+                        .value_name("ID")
+                        .help("The id or e621 url of the post or pool")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("pool")
+                        .short("p")
+                        .long("pool")
+                        .help("Treat the id as a pool id"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Check downloaded files against their md5")
+                .arg(
+                    Arg::with_name("delete")
+                        .long("delete")
+                        .help("Remove corrupt files so they are downloaded again"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("stats").about("Show the size of the archive"))
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Manage the config file")
+                .subcommand(
+                    SubCommand::with_name("init").about("Create a commented config file template"),
+                ),
         )
         .get_matches();
 
+    if let Some(config_matches) = matches.subcommand_matches("config") {
+        if config_matches.subcommand_matches("init").is_some() {
+            let config_file = config_matches
+                .value_of("config")
+                .or_else(|| matches.value_of("config"));
+            settings::init(config_file);
+        } else {
+            println!("{}", config_matches.usage());
+        }
+        return;
+    }
+
     println!("{}", BANNER);
 
-    let mut dir_string = String::new();
+    // Global flags can be given before or after the subcommand
+    let (sub_name, sub_matches) = matches.subcommand();
+    let sub_matches = sub_matches.unwrap_or(&matches);
+    let flag = |name: &str| matches.is_present(name) || sub_matches.is_present(name);
+    let value = |name: &str| {
+        sub_matches
+            .value_of(name)
+            .or_else(|| matches.value_of(name))
+    };
+
+    // Create the config struct we will pass to other functions, values from the config file
+    // are applied first so the command line flags can override them
+    let mut config = unit::Config::new(false, false, None);
 
-    if let Some(dir) = matches.value_of("directory") {
-        dir_string = String::from(dir);
+    let settings = settings::load(value("config"));
+    if let Some(settings) = &settings {
+        settings.apply(&mut config);
     }
 
-    // Create the config struct we will pass to other functions
-    let config = unit::Config::new(
-        matches.is_present("sfw"),
-        matches.is_present("verbose"),
-        Some(dir_string),
-    );
+    if flag("sfw") {
+        config.sfw = true;
+    }
+    if flag("verbose") {
+        config.verbose = true;
+    }
+    if let Some(dir) = value("directory") {
+        config.directory = Some(String::from(dir));
+    }
+    if flag("skip-preflight") {
+        config.preflight = false;
+    }
+    if flag("rewrite-aliases") {
+        config.rewrite_aliases = true;
+    }
+    if flag("sidecar") {
+        config.sidecar = true;
+    }
+    if flag("commentary") {
+        config.commentary = true;
+    }
+    if flag("wiki") {
+        config.wiki = true;
+    }
+    if flag("notes") {
+        config.notes = true;
+    }
+    if flag("note-overlay") {
+        config.notes = true;
+        config.note_overlay = true;
+    }
+    if flag("comments") {
+        config.comments = true;
+    }
+    if flag("sources") {
+        config.sources = true;
+    }
+    if let Some(source_command) = value("source-command") {
+        config.source_command = Some(String::from(source_command));
+    }
+    if flag("incremental") {
+        config.incremental = true;
+    }
+    if flag("prune") {
+        config.prune = true;
+    }
+    if flag("reconstruct-urls") {
+        config.reconstruct_urls = true;
+    }
+    if flag("mirror") {
+        config.mirror = true;
+    }
+    if flag("move-deleted") {
+        config.move_deleted = true;
+    }
+    if flag("discard-replaced") {
+        config.keep_replaced = false;
+    }
+    if flag("dry-run") {
+        config.dry_run = true;
+    }
+    if let Some(limit) = value("limit") {
+        config.limit = Some(
+            limit
+                .parse::<usize>()
+                .expect("[-] Invalid value for the limit"),
+        );
+    }
+    if let Some(order) = value("order") {
+        config.order = order.parse::<unit::Order>().unwrap();
+    }
+    if let Some(variant) = value("variant") {
+        config.variant = variant.parse::<unit::Variant>().unwrap();
+    }
+    if let Some(export) = value("export") {
+        config.export = Some(export.parse::<unit::Export>().unwrap());
+    }
+    if let Some(follow) = value("follow") {
+        config.follow = Some(follow.parse::<unit::Follow>().unwrap());
+    }
+    if let Some(follow_depth) = value("follow-depth") {
+        config.follow_depth = follow_depth
+            .parse::<usize>()
+            .expect("[-] Invalid value for the follow depth");
+    }
+    if let Some(pool_folder) = value("pool-folder") {
+        config.pool_folder = String::from(pool_folder);
+    }
+    if let Some(tries) = value("tries") {
+        config.tries = tries
+            .parse::<usize>()
+            .expect("[-] Invalid value for number of tries");
+    }
+    if let Some(workers) = value("workers") {
+        config.workers = workers
+            .parse::<usize>()
+            .expect("[-] Invalid value for number of workers");
+    }
 
-    // Create the main queue to store Containers in for processing
-    let mut queue = VecDeque::<unit::Container>::new();
+    match sub_name {
+        "search" => {
+            let query = sub_matches.value_of("query").unwrap();
+            commands::search(query, &config).await;
+        }
+        "info" => {
+            let id = sub_matches.value_of("id").unwrap();
+            match link::parse(id) {
+                Some(link::Link::Post(id)) => commands::info(id, false, &config).await,
+                Some(link::Link::Pool(id)) => commands::info(id, true, &config).await,
+                Some(_) => println!("[-] Only post and pool urls can be used with info"),
+                None => {
+                    let id = id
+                        .parse::<u64>()
+                        .expect("[-] Failed to convert id to integer");
+                    commands::info(id, sub_matches.is_present("pool"), &config).await;
+                }
+            }
+        }
+        "get" => {
+            let mut fresh_tags = file::TagStore::new();
+            let values = |name: &str| -> Vec<file::Entry> {
+                sub_matches
+                    .values_of(name)
+                    .map(|v| v.filter(|q| *q != "-").map(file::Entry::parse).collect())
+                    .unwrap_or_default()
+            };
 
-    let fresh_tags =
-        file::read_tags(matches.value_of("tag-file")).expect("[-] Failed to parse tag file");
+            fresh_tags.general = values("query");
+            fresh_tags.pools = values("pool");
+            fresh_tags.single_posts = values("post");
+            fresh_tags.sets = values("set");
 
-    file::check_pop(&fresh_tags);
+            let dash = sub_matches
+                .values_of("query")
+                .is_some_and(|mut v| v.any(|q| q == "-"));
+            if sub_matches.is_present("stdin") || dash {
+                fresh_tags.extend(file::read_stdin().expect("[-] Failed to read stdin"));
+            }
 
-    // Start scraping posts
-    println!("[=] Scraping Posts");
-    for tag in fresh_tags.general {
-        queue.push_back(scraper::build_tag_queue(&tag, &config).await);
-    }
+            file::check_pop(&fresh_tags);
+            commands::download(fresh_tags, &config).await;
+        }
+        "verify" => commands::verify(&config, sub_matches.is_present("delete")),
+        "stats" => commands::stats(&config),
+        _ => {
+            // The tags file is only required when the config file doesn't list any queries
+            let mut fresh_tags = file::TagStore::new();
+            let has_queries = settings.as_ref().is_some_and(|s| s.has_queries());
 
-    for tag in fresh_tags.pools {
-        queue.push_back(
-            scraper::build_pool_queue(
-                tag.parse::<u64>()
-                    .expect("[-] Failed to convert pool id to integer"),
-                &config,
-            )
-            .await,
-        );
-    }
+            if !has_queries || value("tag-file").is_some() || file::default_tag_file_exists() {
+                fresh_tags =
+                    file::read_tags(value("tag-file")).expect("[-] Failed to parse tag file");
+            }
 
-    for tag in fresh_tags.single_posts {
-        queue.push_back(
-            scraper::build_single_post(
-                tag.parse::<u64>()
-                    .expect("[-] Failed to convert pool id to integer"),
-                &config,
-            )
-            .await,
-        );
-    }
+            if let Some(settings) = &settings {
+                fresh_tags.extend(settings.tags());
+            }
+
+            file::check_pop(&fresh_tags);
 
-    let num_tries = matches
-        .value_of("tries")
-        .expect("[-] Failed to parse number of tries")
-        .parse::<usize>()
-        .expect("[-] Invalid value for number of tries");
-    let num_workers = matches
-        .value_of("workers")
-        .expect("[-] Failed to parse number of workers")
-        .parse::<usize>()
-        .expect("[-] Invalid value for number of workers");
-
-    // Start downloading files
-    println!("\n[=] Downloading Files");
-    for q in &mut queue {
-        let down = download::Downloader::new(num_tries, num_workers, &mut q.queue);
-        down.download(&q.tag_name, &config);
+            if sub_name == "sync" {
+                commands::sync(fresh_tags, &config).await;
+            } else {
+                commands::download(fresh_tags, &config).await;
+            }
+        }
     }
 }